@@ -57,6 +57,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             ".openfga.v1.CheckRequest.trace",
             "#[serde(skip_serializing_if = \"Option::is_none\")]",
         )
+        .field_attribute(
+            ".openfga.v1.UpdateStoreRequest.store_id",
+            "#[serde(default, skip_serializing_if = \"String::is_empty\")]",
+        )
+        .field_attribute(
+            ".openfga.v1.StreamedListObjectsRequest.authorization_model_id",
+            "#[serde(default)]",
+        )
         .field_attribute(
             ".openfga.v1.CheckRequest.store_id",
             "#[serde(skip_serializing_if = \"Option::is_none\")]",
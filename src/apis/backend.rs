@@ -0,0 +1,144 @@
+use super::openfga::*;
+use super::UrkelError;
+use rocket::futures::stream::BoxStream;
+use std::fmt;
+use std::time::Duration;
+
+/// A failed call to OpenFGA.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackendError {
+    pub error: UrkelError,
+    /// Set when the call failed before reaching OpenFGA, so sending it again cannot apply it
+    /// twice.
+    pub unsent: bool,
+}
+
+impl BackendError {
+    pub fn new(error: UrkelError) -> BackendError {
+        BackendError {
+            error,
+            unsent: false,
+        }
+    }
+
+    pub fn unsent(error: UrkelError) -> BackendError {
+        BackendError {
+            error,
+            unsent: true,
+        }
+    }
+}
+
+impl From<UrkelError> for BackendError {
+    fn from(error: UrkelError) -> BackendError {
+        BackendError::new(error)
+    }
+}
+
+/// The operations of OpenFGA's API, whichever protocol is used to reach it. Each call is sent
+/// once; `timeout` is the time left before its deadline.
+#[rocket::async_trait]
+pub trait OpenFgaBackend: fmt::Debug + Send + Sync {
+    async fn get_store(
+        &self,
+        request: GetStoreRequest,
+        timeout: Duration,
+    ) -> Result<GetStoreResponse, BackendError>;
+
+    async fn list_stores(
+        &self,
+        request: ListStoresRequest,
+        timeout: Duration,
+    ) -> Result<ListStoresResponse, BackendError>;
+
+    async fn create_store(
+        &self,
+        request: CreateStoreRequest,
+        timeout: Duration,
+    ) -> Result<CreateStoreResponse, BackendError>;
+
+    async fn update_store(
+        &self,
+        request: UpdateStoreRequest,
+        timeout: Duration,
+    ) -> Result<UpdateStoreResponse, BackendError>;
+
+    async fn delete_store(
+        &self,
+        request: DeleteStoreRequest,
+        timeout: Duration,
+    ) -> Result<DeleteStoreResponse, BackendError>;
+
+    async fn read_authorization_models(
+        &self,
+        request: ReadAuthorizationModelsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelsResponse, BackendError>;
+
+    async fn write_authorization_model(
+        &self,
+        request: WriteAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<WriteAuthorizationModelResponse, BackendError>;
+
+    async fn read_authorization_model(
+        &self,
+        request: ReadAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelResponse, BackendError>;
+
+    async fn read_changes(
+        &self,
+        request: ReadChangesRequest,
+        timeout: Duration,
+    ) -> Result<ReadChangesResponse, BackendError>;
+
+    async fn read(
+        &self,
+        request: ReadRequest,
+        timeout: Duration,
+    ) -> Result<ReadResponse, BackendError>;
+
+    async fn write(
+        &self,
+        request: WriteRequest,
+        timeout: Duration,
+    ) -> Result<WriteResponse, BackendError>;
+
+    async fn check(
+        &self,
+        request: CheckRequest,
+        timeout: Duration,
+    ) -> Result<CheckResponse, BackendError>;
+
+    async fn expand(
+        &self,
+        request: ExpandRequest,
+        timeout: Duration,
+    ) -> Result<ExpandResponse, BackendError>;
+
+    async fn list_objects(
+        &self,
+        request: ListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<ListObjectsResponse, BackendError>;
+
+    /// Opens a stream of the objects found by OpenFGA. `timeout` also bounds the whole stream.
+    async fn streamed_list_objects(
+        &self,
+        request: StreamedListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<BoxStream<'static, Result<StreamedListObjectsResponse, BackendError>>, BackendError>;
+
+    async fn read_assertions(
+        &self,
+        request: ReadAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAssertionsResponse, BackendError>;
+
+    async fn write_assertions(
+        &self,
+        request: WriteAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<WriteAssertionsResponse, BackendError>;
+}
@@ -0,0 +1,200 @@
+use super::openfga::{CheckRequest, CheckResponse};
+use crate::config::CacheConfig;
+use prost::Message;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Decisions of recent checks, keyed by everything which can change them: the store, the
+/// authorization model, the tuple key and the contextual tuples. Entries expire after the
+/// configured TTL, and the oldest ones are evicted when the cache is full.
+///
+/// Each store has a generation, bumped whenever its decisions are forgotten. A check captures it
+/// before calling OpenFGA, so that a decision made before a write cannot be cached after it.
+#[derive(Debug)]
+pub struct DecisionCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    decisions: HashMap<Vec<u8>, Decision>,
+    /// Keys of the decisions in insertion order. Expired decisions stay until they are replaced
+    /// or evicted.
+    order: VecDeque<Vec<u8>>,
+    generations: HashMap<String, u64>,
+}
+
+#[derive(Debug)]
+struct Decision {
+    store_id: String,
+    response: CheckResponse,
+    expires_at: Instant,
+}
+
+fn key(request: &CheckRequest) -> Vec<u8> {
+    CheckRequest {
+        trace: None,
+        ..request.clone()
+    }
+    .encode_to_vec()
+}
+
+impl DecisionCache {
+    pub fn new(config: &CacheConfig) -> DecisionCache {
+        DecisionCache {
+            ttl: config.ttl(),
+            max_entries: config.max_entries,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The decision of an identical check made less than a TTL ago.
+    pub fn get(&self, request: &CheckRequest) -> Option<CheckResponse> {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        entries
+            .decisions
+            .get(&key(request))
+            .filter(|decision| decision.expires_at > Instant::now())
+            .map(|decision| decision.response.clone())
+    }
+
+    /// The generation of a store, to capture before calling OpenFGA and pass to `insert`.
+    pub fn generation(&self, store_id: &str) -> u64 {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        entries.generations.get(store_id).copied().unwrap_or(0)
+    }
+
+    /// Caches a decision, unless the decisions of its store were forgotten since `generation`.
+    pub fn insert(&self, request: &CheckRequest, response: CheckResponse, generation: u64) {
+        let key = key(request);
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if entries.generations.get(&store_id).copied().unwrap_or(0) != generation {
+            return;
+        }
+        let decision = Decision {
+            store_id,
+            response,
+            expires_at: Instant::now() + self.ttl,
+        };
+        if entries.decisions.insert(key.clone(), decision).is_none() {
+            entries.order.push_back(key);
+        }
+        while entries.decisions.len() > self.max_entries {
+            match entries.order.pop_front() {
+                Some(oldest) => entries.decisions.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    /// Forgets the decisions of a store, after its tuples or models changed.
+    pub fn invalidate_store(&self, store_id: &str) {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let Entries {
+            decisions,
+            order,
+            generations,
+        } = &mut *entries;
+        decisions.retain(|_, decision| decision.store_id != store_id);
+        order.retain(|key| decisions.contains_key(key));
+        *generations.entry(store_id.to_owned()).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::TupleKey;
+
+    fn cache() -> DecisionCache {
+        DecisionCache::new(&CacheConfig {
+            enabled: true,
+            ttl_ms: 60_000,
+            max_entries: 2,
+        })
+    }
+
+    fn request(store_id: &str, user: &str) -> CheckRequest {
+        CheckRequest {
+            store_id: Some(store_id.to_owned()),
+            tuple_key: Some(TupleKey {
+                object: Some("doc:1".to_owned()),
+                relation: Some("viewer".to_owned()),
+                user: Some(user.to_owned()),
+            }),
+            ..CheckRequest::default()
+        }
+    }
+
+    fn allowed() -> CheckResponse {
+        CheckResponse {
+            allowed: true,
+            ..CheckResponse::default()
+        }
+    }
+
+    #[test]
+    fn answers_identical_checks_but_not_other_ones() {
+        let cache = cache();
+        let check = request("store", "user:a");
+
+        cache.insert(&check, allowed(), cache.generation("store"));
+
+        assert_eq!(cache.get(&check), Some(allowed()));
+        assert_eq!(cache.get(&request("store", "user:b")), None);
+        assert_eq!(cache.get(&request("other", "user:a")), None);
+    }
+
+    #[test]
+    fn evicts_the_oldest_decisions() {
+        let cache = cache();
+        for user in ["user:a", "user:b", "user:c"] {
+            cache.insert(&request("store", user), allowed(), 0);
+        }
+
+        assert_eq!(cache.get(&request("store", "user:a")), None);
+        assert_eq!(cache.get(&request("store", "user:c")), Some(allowed()));
+    }
+
+    #[test]
+    fn forgets_the_decisions_of_a_store() {
+        let cache = cache();
+        cache.insert(&request("store", "user:a"), allowed(), 0);
+        cache.insert(&request("other", "user:a"), allowed(), 0);
+
+        cache.invalidate_store("store");
+
+        assert_eq!(cache.get(&request("store", "user:a")), None);
+        assert_eq!(cache.get(&request("other", "user:a")), Some(allowed()));
+    }
+
+    #[test]
+    fn skips_decisions_made_before_the_store_was_invalidated() {
+        let cache = cache();
+        let check = request("store", "user:a");
+        let generation = cache.generation("store");
+
+        cache.invalidate_store("store");
+        cache.insert(&check, allowed(), generation);
+        assert_eq!(cache.get(&check), None);
+
+        cache.insert(&check, allowed(), cache.generation("store"));
+        assert_eq!(cache.get(&check), Some(allowed()));
+    }
+}
@@ -0,0 +1,187 @@
+use bytes::Bytes;
+use http::HeaderMap;
+use rocket::futures::future::BoxFuture;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::transport::Channel;
+use tower::Service;
+
+/// Metadata key carrying the raw `grpc-status` of a response.
+///
+/// OpenFGA reports most failures with its own status codes (2000 and up), which tonic folds into
+/// `Code::Unknown` while dropping the original header. Headers it does not recognise are kept as
+/// metadata, so a copy stored under this key survives into the `Status`.
+pub const OPENFGA_CODE_KEY: &str = "x-urkel-grpc-status";
+
+/// A tonic channel which copies the `grpc-status` of every response to [`OPENFGA_CODE_KEY`].
+#[derive(Clone, Debug)]
+pub struct OpenFgaChannel<S = Channel> {
+    inner: S,
+}
+
+impl<S> OpenFgaChannel<S> {
+    pub fn new(inner: S) -> OpenFgaChannel<S> {
+        OpenFgaChannel { inner }
+    }
+}
+
+impl<S> Service<http::Request<BoxBody>> for OpenFgaChannel<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<hyper::Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<OpenFgaBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::poll_ready(&mut self.inner, cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let response = Service::call(&mut self.inner, request);
+        Box::pin(async move {
+            // Failures without a body ("trailers-only") carry the status in the headers.
+            let mut response = response.await?;
+            copy_status_code(response.headers_mut());
+            Ok(response.map(|inner| OpenFgaBody { inner }))
+        })
+    }
+}
+
+/// Response body of an [`OpenFgaChannel`], copying the status found in the trailers.
+#[derive(Debug, Default)]
+pub struct OpenFgaBody {
+    inner: hyper::Body,
+}
+
+impl http_body::Body for OpenFgaBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_trailers(cx)
+            .map_ok(|trailers| {
+                trailers.map(|mut trailers| {
+                    copy_status_code(&mut trailers);
+                    trailers
+                })
+            })
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn copy_status_code(headers: &mut HeaderMap) {
+    if let Some(code) = headers.get("grpc-status").cloned() {
+        headers.insert(OPENFGA_CODE_KEY, code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderName, HeaderValue};
+    use http_body::Body;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tower::{service_fn, ServiceExt};
+
+    fn header_map(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    /// Sends a request through an [`OpenFgaChannel`] over a service answering with `headers`, and
+    /// with `trailers` after an empty body.
+    async fn call(
+        headers: &[(&'static str, &'static str)],
+        trailers: Option<&[(&'static str, &'static str)]>,
+    ) -> http::Response<OpenFgaBody> {
+        let body = match trailers {
+            Some(trailers) => {
+                let (mut sender, body) = hyper::Body::channel();
+                let trailers = header_map(trailers);
+                rocket::tokio::spawn(async move { sender.send_trailers(trailers).await });
+                body
+            }
+            None => hyper::Body::empty(),
+        };
+        let mut response = http::Response::new(body);
+        *response.headers_mut() = header_map(headers);
+
+        let response = Mutex::new(Some(response));
+        let inner = service_fn(move |_: http::Request<BoxBody>| {
+            let response = response.lock().unwrap_or_else(|e| e.into_inner()).take();
+            async move { Ok::<_, Infallible>(response.unwrap()) }
+        });
+        OpenFgaChannel::new(inner)
+            .oneshot(http::Request::new(tonic::body::empty_body()))
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn copies_the_status_of_trailers_only_responses() {
+        let response = call(&[("grpc-status", "2001"), ("grpc-message", "boom")], None).await;
+        assert_eq!(response.headers()[OPENFGA_CODE_KEY], "2001");
+        assert_eq!(response.headers()["grpc-status"], "2001");
+    }
+
+    #[rocket::async_test]
+    async fn copies_the_status_of_the_trailers() {
+        let mut response = call(
+            &[("content-type", "application/grpc")],
+            Some(&[("grpc-status", "5002")]),
+        )
+        .await;
+        assert!(response.headers().get(OPENFGA_CODE_KEY).is_none());
+
+        let body = response.body_mut();
+        while let Some(data) = std::future::poll_fn(|cx| Pin::new(&mut *body).poll_data(cx)).await {
+            data.unwrap();
+        }
+        let trailers = std::future::poll_fn(|cx| Pin::new(&mut *body).poll_trailers(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(trailers[OPENFGA_CODE_KEY], "5002");
+        assert_eq!(trailers["grpc-status"], "5002");
+    }
+
+    #[rocket::async_test]
+    async fn leaves_responses_without_a_status_alone() {
+        let mut response = call(&[("content-type", "application/grpc")], Some(&[])).await;
+        assert!(response.headers().get(OPENFGA_CODE_KEY).is_none());
+        let trailers = std::future::poll_fn(|cx| Pin::new(response.body_mut()).poll_trailers(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(trailers.get(OPENFGA_CODE_KEY).is_none());
+    }
+}
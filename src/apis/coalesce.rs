@@ -0,0 +1,288 @@
+use super::openfga::{CheckRequest, CheckResponse};
+use super::UrkelError;
+use crate::models::InternalErrorCode;
+use prost::Message;
+use rocket::futures::{
+    future::{BoxFuture, Shared},
+    Future, FutureExt,
+};
+use rocket::tokio;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Flight = Shared<BoxFuture<'static, Result<CheckResponse, UrkelError>>>;
+
+/// How the checks of a client were answered since it was built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CheckMetrics {
+    /// Checks sent to OpenFGA.
+    #[serde(rename = "upstream")]
+    pub upstream: u64,
+    /// Checks which joined an identical check in flight instead.
+    #[serde(rename = "coalesced")]
+    pub coalesced: u64,
+    /// Checks answered from the decision cache.
+    #[serde(rename = "cached")]
+    pub cached: u64,
+}
+
+/// The checks in flight, keyed by the whole request: concurrent identical checks share a single
+/// call to OpenFGA and its outcome, errors included. A check is only shared while in flight, so
+/// its decision is never older than the call of the first caller.
+///
+/// Each store has a generation, bumped by `invalidate_store` after its tuples or models change,
+/// so that a check made after a write never joins one made before it, with or without a
+/// decision cache.
+#[derive(Default)]
+pub struct CheckCoalescer {
+    flights: Mutex<Flights>,
+    upstream: AtomicU64,
+    coalesced: AtomicU64,
+    cached: AtomicU64,
+}
+
+#[derive(Default)]
+struct Flights {
+    in_flight: HashMap<Vec<u8>, Flight>,
+    generations: HashMap<String, u64>,
+}
+
+impl fmt::Debug for CheckCoalescer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CheckCoalescer")
+            .field("in_flight", &self.lock().in_flight.len())
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+impl CheckCoalescer {
+    /// Joins the identical check in flight, or else starts `call`. The call runs in its own task,
+    /// so it finishes and leaves the checks in flight even when every caller gives up on it.
+    /// Only checks with the same `deadline` are shared, as the call of the first caller runs
+    /// under its deadline; `None` stands for the configured one.
+    pub async fn check<Fut>(
+        self: &Arc<Self>,
+        request: &CheckRequest,
+        deadline: Option<Duration>,
+        call: Fut,
+    ) -> Result<CheckResponse, UrkelError>
+    where
+        Fut: Future<Output = Result<CheckResponse, UrkelError>> + Send + 'static,
+    {
+        let mut key = request.encode_to_vec();
+        if let Some(deadline) = deadline {
+            key.extend_from_slice(&deadline.as_nanos().to_be_bytes());
+        }
+        let flight = {
+            let mut flights = self.lock();
+            let store_id = request.store_id.as_deref().unwrap_or("");
+            let generation = flights.generations.get(store_id).copied().unwrap_or(0);
+            key.extend_from_slice(&generation.to_be_bytes());
+            match flights.in_flight.get(&key) {
+                Some(flight) => {
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    flight.clone()
+                }
+                None => {
+                    self.upstream.fetch_add(1, Ordering::Relaxed);
+                    let coalescer = self.clone();
+                    let landed = key.clone();
+                    // Removing the entry waits for the lock, held until the entry is inserted.
+                    let task = tokio::spawn(async move {
+                        let response = call.await;
+                        coalescer.lock().in_flight.remove(&landed);
+                        response
+                    });
+                    let flight = async move {
+                        task.await.unwrap_or_else(|error| {
+                            Err(UrkelError::internal(
+                                InternalErrorCode::InternalError,
+                                format!("The check did not complete: {}", error),
+                            ))
+                        })
+                    }
+                    .boxed()
+                    .shared();
+                    flights.in_flight.insert(key, flight.clone());
+                    flight
+                }
+            }
+        };
+        flight.await
+    }
+
+    /// Keeps the checks of a store whose tuples or models may have changed from joining those
+    /// already in flight.
+    pub fn invalidate_store(&self, store_id: &str) {
+        *self
+            .lock()
+            .generations
+            .entry(store_id.to_owned())
+            .or_default() += 1;
+    }
+
+    /// Counts a check answered from the decision cache.
+    pub fn cached(&self) {
+        self.cached.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn metrics(&self) -> CheckMetrics {
+        CheckMetrics {
+            upstream: self.upstream.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            cached: self.cached.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Flights> {
+        self.flights
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::TupleKey;
+    use rocket::futures::future::join;
+    use rocket::tokio::sync::oneshot;
+
+    fn request(user: &str) -> CheckRequest {
+        CheckRequest {
+            store_id: Some("store".to_owned()),
+            tuple_key: Some(TupleKey {
+                object: Some("doc:1".to_owned()),
+                relation: Some("viewer".to_owned()),
+                user: Some(user.to_owned()),
+            }),
+            ..CheckRequest::default()
+        }
+    }
+
+    async fn answer(allowed: bool) -> Result<CheckResponse, UrkelError> {
+        Ok(CheckResponse {
+            allowed,
+            ..CheckResponse::default()
+        })
+    }
+
+    /// A call answering `allowed` once `release` is sent, counting the calls made.
+    fn call(
+        calls: &Arc<AtomicU64>,
+        allowed: bool,
+        release: oneshot::Receiver<()>,
+    ) -> impl Future<Output = Result<CheckResponse, UrkelError>> {
+        let calls = calls.clone();
+        async move {
+            calls.fetch_add(1, Ordering::Relaxed);
+            let _ = release.await;
+            answer(allowed).await
+        }
+    }
+
+    #[rocket::async_test]
+    async fn shares_identical_checks_in_flight() {
+        let coalescer = Arc::new(CheckCoalescer::default());
+        let calls = Arc::new(AtomicU64::new(0));
+        let (release, released) = oneshot::channel();
+        let request = request("user:a");
+
+        let first = coalescer.check(&request, None, call(&calls, true, released));
+        let second = coalescer.check(&request, None, answer(false));
+        let (first, second) = join(first, async {
+            let second = join(second, async { release.send(()).unwrap() }).await;
+            second.0
+        })
+        .await;
+
+        assert!(first.unwrap().allowed);
+        assert!(second.unwrap().allowed);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            coalescer.metrics(),
+            CheckMetrics {
+                upstream: 1,
+                coalesced: 1,
+                cached: 0
+            }
+        );
+    }
+
+    #[rocket::async_test]
+    async fn does_not_share_checks_across_writes() {
+        let coalescer = Arc::new(CheckCoalescer::default());
+        let calls = Arc::new(AtomicU64::new(0));
+        let (release, released) = oneshot::channel();
+        let request = request("user:a");
+
+        let before = coalescer.check(&request, None, call(&calls, true, released));
+        let after = async {
+            coalescer.invalidate_store("store");
+            let after = coalescer.check(&request, None, answer(false)).await;
+            release.send(()).unwrap();
+            after
+        };
+        let (before, after) = join(before, after).await;
+
+        assert!(before.unwrap().allowed);
+        assert!(!after.unwrap().allowed);
+        assert_eq!(coalescer.metrics().upstream, 2);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_share_checks_across_deadlines() {
+        let coalescer = Arc::new(CheckCoalescer::default());
+        let calls = Arc::new(AtomicU64::new(0));
+        let (release, released) = oneshot::channel();
+        let request = request("user:a");
+
+        let configured = coalescer.check(&request, None, call(&calls, true, released));
+        let shorter = async {
+            let shorter = coalescer
+                .check(&request, Some(Duration::from_millis(50)), answer(false))
+                .await;
+            release.send(()).unwrap();
+            shorter
+        };
+        let (configured, shorter) = join(configured, shorter).await;
+
+        assert!(configured.unwrap().allowed);
+        assert!(!shorter.unwrap().allowed);
+        assert_eq!(coalescer.metrics().upstream, 2);
+    }
+
+    #[rocket::async_test]
+    async fn forgets_checks_whose_callers_gave_up() {
+        let coalescer = Arc::new(CheckCoalescer::default());
+        let calls = Arc::new(AtomicU64::new(0));
+        let (release, released) = oneshot::channel();
+
+        let abandoned = tokio::time::timeout(
+            Duration::from_millis(20),
+            coalescer.check(&request("user:a"), None, call(&calls, true, released)),
+        )
+        .await;
+        assert!(abandoned.is_err());
+        release.send(()).unwrap();
+        let landed = async {
+            while !coalescer.lock().in_flight.is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        let _ = tokio::time::timeout(Duration::from_secs(1), landed).await;
+
+        let later = coalescer
+            .check(&request("user:a"), None, answer(false))
+            .await
+            .unwrap();
+
+        assert!(!later.allowed);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(coalescer.metrics().upstream, 2);
+    }
+}
@@ -0,0 +1,396 @@
+use super::UrkelError;
+use crate::config::{CredentialsConfig, CredentialsMethod};
+use crate::models::InternalErrorCode;
+use rocket::tokio::sync::Mutex;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    Request, Status,
+};
+
+/// Lifetime assumed for access tokens whose response does not carry `expires_in`.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+/// Tokens are refreshed once they are this close to expiring, or halfway through shorter lifetimes.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Where the `authorization` header sent to OpenFGA comes from.
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    None,
+    ApiToken(MetadataValue<Ascii>),
+    ClientCredentials(Arc<ClientCredentials>),
+}
+
+impl Credentials {
+    pub fn from_config(
+        config: &CredentialsConfig,
+    ) -> Result<Credentials, Box<dyn Error + Send + Sync>> {
+        match config.method {
+            CredentialsMethod::None => Ok(Credentials::None),
+            CredentialsMethod::ApiToken => {
+                let token = config.api_token.as_deref().unwrap_or_default();
+                Ok(Credentials::ApiToken(bearer(token)?))
+            }
+            CredentialsMethod::ClientCredentials => Ok(Credentials::ClientCredentials(Arc::new(
+                ClientCredentials::new(config),
+            ))),
+        }
+    }
+
+    /// Makes sure a usable token is cached before a call goes out.
+    pub async fn refresh(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Credentials::ClientCredentials(client_credentials) => {
+                client_credentials.refresh().await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The header value for the next request, if any.
+    pub fn authorization(&self) -> Option<MetadataValue<Ascii>> {
+        match self {
+            Credentials::None => None,
+            Credentials::ApiToken(authorization) => Some(authorization.clone()),
+            Credentials::ClientCredentials(client_credentials) => {
+                client_credentials.authorization()
+            }
+        }
+    }
+
+    /// The header value for the next request, failing when credentials are configured but no
+    /// token is available.
+    pub fn required_authorization(&self) -> Result<Option<MetadataValue<Ascii>>, UrkelError> {
+        match (self, self.authorization()) {
+            (_, Some(authorization)) => Ok(Some(authorization)),
+            (Credentials::None, None) => Ok(None),
+            (_, None) => Err(UrkelError::internal(
+                InternalErrorCode::Unavailable,
+                "No OpenFGA access token is available.",
+            )),
+        }
+    }
+}
+
+/// Attaches the current OpenFGA credentials to every outgoing request.
+#[derive(Clone, Debug)]
+pub struct AuthInterceptor {
+    credentials: Credentials,
+}
+
+impl AuthInterceptor {
+    pub fn new(credentials: Credentials) -> AuthInterceptor {
+        AuthInterceptor { credentials }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = self
+            .credentials
+            .required_authorization()
+            .map_err(|error| Status::unavailable(error.to_string()))?;
+        if let Some(authorization) = authorization {
+            req.metadata_mut().insert("authorization", authorization);
+        }
+        Ok(req)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CachedToken {
+    authorization: MetadataValue<Ascii>,
+    refresh_at: Instant,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// OAuth2 client-credentials grant against a token endpoint, with the access token cached until
+/// shortly before it expires.
+pub struct ClientCredentials {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    audience: Option<String>,
+    scopes: Vec<String>,
+    http: reqwest::Client,
+    token: RwLock<Option<CachedToken>>,
+    refreshing: Mutex<()>,
+}
+
+impl fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("audience", &self.audience)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientCredentials {
+    pub fn new(config: &CredentialsConfig) -> ClientCredentials {
+        ClientCredentials {
+            token_url: config.token_url.clone().unwrap_or_default(),
+            client_id: config.client_id.clone().unwrap_or_default(),
+            client_secret: config.client_secret.clone().unwrap_or_default(),
+            audience: config.audience.clone(),
+            scopes: config.scopes.clone(),
+            http: reqwest::Client::new(),
+            token: RwLock::new(None),
+            refreshing: Mutex::new(()),
+        }
+    }
+
+    fn cached(&self) -> Option<CachedToken> {
+        self.token
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn authorization(&self) -> Option<MetadataValue<Ascii>> {
+        self.cached()
+            .filter(|token| Instant::now() < token.expires_at)
+            .map(|token| token.authorization)
+    }
+
+    fn needs_refresh(&self) -> bool {
+        match self.cached() {
+            Some(token) => Instant::now() >= token.refresh_at,
+            None => true,
+        }
+    }
+
+    /// Fetches a new token when the cached one is missing or about to expire. Concurrent callers
+    /// wait for a single request to the token endpoint. A failed refresh is only reported while
+    /// the previous token can no longer be used.
+    pub async fn refresh(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.needs_refresh() {
+            return Ok(());
+        }
+        let _guard = self.refreshing.lock().await;
+        if !self.needs_refresh() {
+            return Ok(());
+        }
+
+        match self.fetch().await {
+            Ok(token) => {
+                *self
+                    .token
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(token);
+                Ok(())
+            }
+            Err(error) if self.authorization().is_some() => {
+                eprintln!("OpenFGA token refresh failed, reusing current token: {error}");
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    async fn fetch(&self) -> Result<CachedToken, Box<dyn Error + Send + Sync>> {
+        let mut form = vec![
+            ("grant_type", "client_credentials".to_owned()),
+            ("client_id", self.client_id.clone()),
+            ("client_secret", self.client_secret.clone()),
+        ];
+        if let Some(audience) = &self.audience {
+            form.push(("audience", audience.clone()));
+        }
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+
+        let issued_at = Instant::now();
+        let response = self.http.post(&self.token_url).form(&form).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("token endpoint returned {}: {}", status, body).into());
+        }
+        let token: TokenResponse = response.json().await?;
+
+        let lifetime = token
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+        let margin = REFRESH_MARGIN.min(lifetime / 2);
+        Ok(CachedToken {
+            authorization: bearer(&token.access_token)?,
+            refresh_at: issued_at + lifetime - margin,
+            expires_at: issued_at + lifetime,
+        })
+    }
+}
+
+fn bearer(token: &str) -> Result<MetadataValue<Ascii>, Box<dyn Error + Send + Sync>> {
+    Ok(format!("Bearer {}", token).parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::futures::future::join_all;
+    use rocket::tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// A token endpoint answering its requests with `responses` in turn, then refusing
+    /// connections. Returns its URL and the forms it received.
+    async fn token_endpoint(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/oauth/token", listener.local_addr().unwrap());
+        let forms = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = forms.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let form = read_body(&mut stream).await;
+                received.lock().unwrap().push(form);
+                let response = format!(
+                    "HTTP/1.1 {} Token\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, forms)
+    }
+
+    async fn read_body(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length || read == 0 {
+                    return body.to_owned();
+                }
+            }
+        }
+    }
+
+    fn client_credentials(token_url: &str) -> ClientCredentials {
+        ClientCredentials::new(&CredentialsConfig {
+            method: CredentialsMethod::ClientCredentials,
+            token_url: Some(token_url.to_owned()),
+            client_id: Some("urkel".to_owned()),
+            client_secret: Some("secret".to_owned()),
+            audience: Some("openfga".to_owned()),
+            scopes: vec!["read".to_owned(), "write".to_owned()],
+            ..CredentialsConfig::default()
+        })
+    }
+
+    fn authorization(credentials: &ClientCredentials) -> Option<String> {
+        credentials
+            .authorization()
+            .map(|authorization| authorization.to_str().unwrap().to_owned())
+    }
+
+    #[rocket::async_test]
+    async fn caches_the_token_until_it_needs_refreshing() {
+        let (url, forms) =
+            token_endpoint(vec![(200, r#"{"access_token":"first","expires_in":3600}"#)]).await;
+        let credentials = client_credentials(&url);
+        assert_eq!(authorization(&credentials), None);
+
+        for refreshed in join_all((0..3).map(|_| credentials.refresh())).await {
+            refreshed.unwrap();
+        }
+        credentials.refresh().await.unwrap();
+
+        assert_eq!(authorization(&credentials).as_deref(), Some("Bearer first"));
+        let forms = forms.lock().unwrap();
+        assert_eq!(forms.len(), 1);
+        for field in [
+            "grant_type=client_credentials",
+            "client_id=urkel",
+            "client_secret=secret",
+            "audience=openfga",
+            "scope=read+write",
+        ] {
+            assert!(forms[0].contains(field), "{} not in {}", field, forms[0]);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn refreshes_the_token_before_it_expires() {
+        let (url, forms) = token_endpoint(vec![
+            (200, r#"{"access_token":"first","expires_in":1}"#),
+            (200, r#"{"access_token":"second","expires_in":1}"#),
+        ])
+        .await;
+        let credentials = client_credentials(&url);
+
+        credentials.refresh().await.unwrap();
+        assert_eq!(authorization(&credentials).as_deref(), Some("Bearer first"));
+        // Refreshed halfway through lifetimes shorter than twice the margin.
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        credentials.refresh().await.unwrap();
+
+        assert_eq!(
+            authorization(&credentials).as_deref(),
+            Some("Bearer second")
+        );
+        assert_eq!(forms.lock().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn reuses_the_current_token_when_a_refresh_fails() {
+        let (url, forms) = token_endpoint(vec![
+            (200, r#"{"access_token":"first","expires_in":2}"#),
+            (500, r#"{"error":"unavailable"}"#),
+        ])
+        .await;
+        let credentials = client_credentials(&url);
+
+        credentials.refresh().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1_100)).await;
+        credentials.refresh().await.unwrap();
+
+        assert_eq!(authorization(&credentials).as_deref(), Some("Bearer first"));
+        assert_eq!(forms.lock().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn fails_when_no_token_can_be_used() {
+        let (url, _) = token_endpoint(vec![(500, r#"{"error":"unavailable"}"#)]).await;
+        let credentials = Credentials::ClientCredentials(Arc::new(client_credentials(&url)));
+
+        let error = credentials.refresh().await.unwrap_err();
+
+        assert!(error.to_string().contains("500"), "{}", error);
+        assert!(credentials.required_authorization().is_err());
+    }
+}
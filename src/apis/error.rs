@@ -0,0 +1,347 @@
+use super::channel::OPENFGA_CODE_KEY;
+use crate::models::{
+    ErrorCode, InternalErrorCode, InternalErrorMessageResponse, NotFoundErrorCode,
+    PathUnknownErrorMessageResponse, ValidationErrorMessageResponse,
+};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+/// An error returned by an OpenFGA call. It serializes to the same `{ code, message }` bodies
+/// OpenFGA's HTTP API uses, so callers can tell bad input apart from an unavailable server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UrkelError {
+    Validation(ValidationErrorMessageResponse),
+    NotFound(PathUnknownErrorMessageResponse),
+    Internal(InternalErrorMessageResponse),
+}
+
+impl UrkelError {
+    pub fn validation(code: ErrorCode, message: impl Into<String>) -> UrkelError {
+        UrkelError::Validation(ValidationErrorMessageResponse {
+            code: Some(code),
+            message: Some(message.into()),
+        })
+    }
+
+    pub fn not_found(code: NotFoundErrorCode, message: impl Into<String>) -> UrkelError {
+        UrkelError::NotFound(PathUnknownErrorMessageResponse {
+            code: Some(code),
+            message: Some(message.into()),
+        })
+    }
+
+    pub fn internal(code: InternalErrorCode, message: impl Into<String>) -> UrkelError {
+        UrkelError::Internal(InternalErrorMessageResponse {
+            code: Some(code),
+            message: Some(message.into()),
+        })
+    }
+
+    /// An operation which did not complete within its deadline.
+    pub fn deadline_exceeded(timeout: Duration) -> UrkelError {
+        UrkelError::internal(
+            InternalErrorCode::DeadlineExceeded,
+            format!("OpenFGA did not answer within {} ms.", timeout.as_millis()),
+        )
+    }
+
+    /// The HTTP status code OpenFGA's HTTP API would answer with.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            UrkelError::Validation(_) => 400,
+            UrkelError::NotFound(error) => match error.code {
+                Some(NotFoundErrorCode::Unimplemented) => 501,
+                _ => 404,
+            },
+            UrkelError::Internal(error) => match error.code.unwrap_or_default() {
+                InternalErrorCode::FailedPrecondition | InternalErrorCode::OutOfRange => 400,
+                InternalErrorCode::AlreadyExists | InternalErrorCode::Aborted => 409,
+                InternalErrorCode::ResourceExhausted => 429,
+                InternalErrorCode::Unavailable => 503,
+                InternalErrorCode::DeadlineExceeded => 504,
+                _ => 500,
+            },
+        }
+    }
+}
+
+impl fmt::Display for UrkelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (code, message) = match self {
+            UrkelError::Validation(error) => {
+                (error.code.unwrap_or_default().to_string(), &error.message)
+            }
+            UrkelError::NotFound(error) => {
+                (error.code.unwrap_or_default().to_string(), &error.message)
+            }
+            UrkelError::Internal(error) => {
+                (error.code.unwrap_or_default().to_string(), &error.message)
+            }
+        };
+        write!(f, "{}: {}", code, message.as_deref().unwrap_or_default())
+    }
+}
+
+impl Error for UrkelError {}
+
+impl From<ValidationErrorMessageResponse> for UrkelError {
+    fn from(error: ValidationErrorMessageResponse) -> UrkelError {
+        UrkelError::Validation(error)
+    }
+}
+
+impl From<Status> for UrkelError {
+    fn from(status: Status) -> UrkelError {
+        UrkelError::from(&status)
+    }
+}
+
+impl From<&Status> for UrkelError {
+    fn from(status: &Status) -> UrkelError {
+        let message = status.message().to_owned();
+        let code = status
+            .metadata()
+            .get(OPENFGA_CODE_KEY)
+            .and_then(|code| code.to_str().ok())
+            .and_then(|code| code.parse::<i32>().ok())
+            .unwrap_or(status.code() as i32);
+
+        match code {
+            0..=16 => from_grpc_code(Code::from_i32(code), message),
+            1000..=1999 => UrkelError::internal(
+                InternalErrorCode::InternalError,
+                format!("OpenFGA rejected the configured credentials: {}", message),
+            ),
+            2000..=2999 => UrkelError::validation(
+                error_code(code).unwrap_or(ErrorCode::ValidationError),
+                message,
+            ),
+            4000..=4999 => UrkelError::internal(
+                internal_error_code(code).unwrap_or(InternalErrorCode::InternalError),
+                message,
+            ),
+            5000..=5999 => UrkelError::not_found(
+                not_found_error_code(code).unwrap_or(NotFoundErrorCode::UndefinedEndpoint),
+                message,
+            ),
+            _ => UrkelError::internal(InternalErrorCode::InternalError, message),
+        }
+    }
+}
+
+/// Maps the standard gRPC codes, as sent by OpenFGA's request validation or by the transport.
+fn from_grpc_code(code: Code, message: String) -> UrkelError {
+    match code {
+        Code::InvalidArgument => UrkelError::validation(ErrorCode::ValidationError, message),
+        Code::NotFound => UrkelError::not_found(NotFoundErrorCode::StoreIdNotFound, message),
+        Code::Unimplemented => UrkelError::not_found(NotFoundErrorCode::Unimplemented, message),
+        Code::Cancelled => UrkelError::internal(InternalErrorCode::Cancelled, message),
+        Code::DeadlineExceeded => {
+            UrkelError::internal(InternalErrorCode::DeadlineExceeded, message)
+        }
+        Code::AlreadyExists => UrkelError::internal(InternalErrorCode::AlreadyExists, message),
+        Code::ResourceExhausted => {
+            UrkelError::internal(InternalErrorCode::ResourceExhausted, message)
+        }
+        Code::FailedPrecondition => {
+            UrkelError::internal(InternalErrorCode::FailedPrecondition, message)
+        }
+        Code::Aborted => UrkelError::internal(InternalErrorCode::Aborted, message),
+        Code::OutOfRange => UrkelError::internal(InternalErrorCode::OutOfRange, message),
+        Code::Unavailable => UrkelError::internal(InternalErrorCode::Unavailable, message),
+        Code::DataLoss => UrkelError::internal(InternalErrorCode::DataLoss, message),
+        Code::Unauthenticated | Code::PermissionDenied => UrkelError::internal(
+            InternalErrorCode::InternalError,
+            format!("OpenFGA rejected the configured credentials: {}", message),
+        ),
+        _ => UrkelError::internal(InternalErrorCode::InternalError, message),
+    }
+}
+
+/// Numbering from `openfga/v1/errors_ignore.proto`.
+fn error_code(code: i32) -> Option<ErrorCode> {
+    let error_code = match code {
+        2000 => ErrorCode::ValidationError,
+        2001 => ErrorCode::AuthorizationModelNotFound,
+        2002 => ErrorCode::AuthorizationModelResolutionTooComplex,
+        2003 => ErrorCode::InvalidWriteInput,
+        2004 => ErrorCode::CannotAllowDuplicateTuplesInOneRequest,
+        2005 => ErrorCode::CannotAllowDuplicateTypesInOneRequest,
+        2006 => ErrorCode::CannotAllowMultipleReferencesToOneRelation,
+        2007 => ErrorCode::InvalidContinuationToken,
+        2008 => ErrorCode::InvalidTupleSet,
+        2009 => ErrorCode::InvalidCheckInput,
+        2010 => ErrorCode::InvalidExpandInput,
+        2011 => ErrorCode::UnsupportedUserSet,
+        2012 => ErrorCode::InvalidObjectFormat,
+        2017 => ErrorCode::WriteFailedDueToInvalidInput,
+        2018 => ErrorCode::AuthorizationModelAssertionsNotFound,
+        2020 => ErrorCode::LatestAuthorizationModelNotFound,
+        2021 => ErrorCode::TypeNotFound,
+        2022 => ErrorCode::RelationNotFound,
+        2023 => ErrorCode::EmptyRelationDefinition,
+        2025 => ErrorCode::InvalidUser,
+        2027 => ErrorCode::InvalidTuple,
+        2028 => ErrorCode::UnknownRelation,
+        2030 => ErrorCode::StoreIdInvalidLength,
+        2033 => ErrorCode::AssertionsTooManyItems,
+        2034 => ErrorCode::IdTooLong,
+        2036 => ErrorCode::AuthorizationModelIdTooLong,
+        2037 => ErrorCode::TupleKeyValueNotSpecified,
+        2038 => ErrorCode::TupleKeysTooManyOrTooFewItems,
+        2039 => ErrorCode::PageSizeInvalid,
+        2040 => ErrorCode::ParamMissingValue,
+        2041 => ErrorCode::DifferenceBaseMissingValue,
+        2042 => ErrorCode::SubtractBaseMissingValue,
+        2043 => ErrorCode::ObjectTooLong,
+        2044 => ErrorCode::RelationTooLong,
+        2045 => ErrorCode::TypeDefinitionsTooFewItems,
+        2046 => ErrorCode::TypeInvalidLength,
+        2047 => ErrorCode::TypeInvalidPattern,
+        2048 => ErrorCode::RelationsTooFewItems,
+        2049 => ErrorCode::RelationsTooLong,
+        2050 => ErrorCode::RelationsInvalidPattern,
+        2051 => ErrorCode::ObjectInvalidPattern,
+        2052 => ErrorCode::QueryStringTypeContinuationTokenMismatch,
+        2053 => ErrorCode::ExceededEntityLimit,
+        2054 => ErrorCode::InvalidContextualTuple,
+        2055 => ErrorCode::DuplicateContextualTuple,
+        2056 => ErrorCode::InvalidAuthorizationModel,
+        2057 => ErrorCode::UnsupportedSchemaVersion,
+        _ => return None,
+    };
+    Some(error_code)
+}
+
+fn internal_error_code(code: i32) -> Option<InternalErrorCode> {
+    let internal_error_code = match code {
+        4000 => InternalErrorCode::InternalError,
+        4003 => InternalErrorCode::Cancelled,
+        4004 => InternalErrorCode::DeadlineExceeded,
+        4005 => InternalErrorCode::AlreadyExists,
+        4006 => InternalErrorCode::ResourceExhausted,
+        4007 => InternalErrorCode::FailedPrecondition,
+        4008 => InternalErrorCode::Aborted,
+        4009 => InternalErrorCode::OutOfRange,
+        4010 => InternalErrorCode::Unavailable,
+        4011 => InternalErrorCode::DataLoss,
+        _ => return None,
+    };
+    Some(internal_error_code)
+}
+
+fn not_found_error_code(code: i32) -> Option<NotFoundErrorCode> {
+    let not_found_error_code = match code {
+        5000 => NotFoundErrorCode::UndefinedEndpoint,
+        5002 => NotFoundErrorCode::StoreIdNotFound,
+        5004 => NotFoundErrorCode::Unimplemented,
+        _ => return None,
+    };
+    Some(not_found_error_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A status as tonic surfaces it: the standard codes as they are, and OpenFGA's own codes
+    /// folded into `Unknown` with the original kept under [`OPENFGA_CODE_KEY`].
+    fn status(code: i32) -> Status {
+        if (0..=16).contains(&code) {
+            return Status::new(Code::from_i32(code), "boom");
+        }
+        let mut status = Status::new(Code::Unknown, "boom");
+        status
+            .metadata_mut()
+            .insert(OPENFGA_CODE_KEY, code.to_string().parse().unwrap());
+        status
+    }
+
+    fn credentials() -> UrkelError {
+        UrkelError::internal(
+            InternalErrorCode::InternalError,
+            "OpenFGA rejected the configured credentials: boom",
+        )
+    }
+
+    #[test]
+    fn maps_status_codes_to_errors_and_http_statuses() {
+        let validation = |code| UrkelError::validation(code, "boom");
+        let not_found = |code| UrkelError::not_found(code, "boom");
+        let internal = |code| UrkelError::internal(code, "boom");
+        let cases = [
+            (0, internal(InternalErrorCode::InternalError), 500),
+            (1, internal(InternalErrorCode::Cancelled), 500),
+            (2, internal(InternalErrorCode::InternalError), 500),
+            (3, validation(ErrorCode::ValidationError), 400),
+            (4, internal(InternalErrorCode::DeadlineExceeded), 504),
+            (5, not_found(NotFoundErrorCode::StoreIdNotFound), 404),
+            (6, internal(InternalErrorCode::AlreadyExists), 409),
+            (7, credentials(), 500),
+            (8, internal(InternalErrorCode::ResourceExhausted), 429),
+            (9, internal(InternalErrorCode::FailedPrecondition), 400),
+            (10, internal(InternalErrorCode::Aborted), 409),
+            (11, internal(InternalErrorCode::OutOfRange), 400),
+            (12, not_found(NotFoundErrorCode::Unimplemented), 501),
+            (13, internal(InternalErrorCode::InternalError), 500),
+            (14, internal(InternalErrorCode::Unavailable), 503),
+            (15, internal(InternalErrorCode::DataLoss), 500),
+            (16, credentials(), 500),
+            (1000, credentials(), 500),
+            (1999, credentials(), 500),
+            (2000, validation(ErrorCode::ValidationError), 400),
+            (2001, validation(ErrorCode::AuthorizationModelNotFound), 400),
+            (2007, validation(ErrorCode::InvalidContinuationToken), 400),
+            (2057, validation(ErrorCode::UnsupportedSchemaVersion), 400),
+            (2999, validation(ErrorCode::ValidationError), 400),
+            (3000, internal(InternalErrorCode::InternalError), 500),
+            (4000, internal(InternalErrorCode::InternalError), 500),
+            (4003, internal(InternalErrorCode::Cancelled), 500),
+            (4004, internal(InternalErrorCode::DeadlineExceeded), 504),
+            (4005, internal(InternalErrorCode::AlreadyExists), 409),
+            (4006, internal(InternalErrorCode::ResourceExhausted), 429),
+            (4007, internal(InternalErrorCode::FailedPrecondition), 400),
+            (4008, internal(InternalErrorCode::Aborted), 409),
+            (4009, internal(InternalErrorCode::OutOfRange), 400),
+            (4010, internal(InternalErrorCode::Unavailable), 503),
+            (4011, internal(InternalErrorCode::DataLoss), 500),
+            (4999, internal(InternalErrorCode::InternalError), 500),
+            (5000, not_found(NotFoundErrorCode::UndefinedEndpoint), 404),
+            (5002, not_found(NotFoundErrorCode::StoreIdNotFound), 404),
+            (5004, not_found(NotFoundErrorCode::Unimplemented), 501),
+            (5999, not_found(NotFoundErrorCode::UndefinedEndpoint), 404),
+            (6000, internal(InternalErrorCode::InternalError), 500),
+        ];
+
+        for (code, expected, http_status) in cases {
+            let error = UrkelError::from(status(code));
+            assert_eq!(error, expected, "gRPC code {}", code);
+            assert_eq!(error.http_status(), http_status, "gRPC code {}", code);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_status_code_without_a_valid_copy() {
+        let mut status = Status::new(Code::NotFound, "boom");
+        status
+            .metadata_mut()
+            .insert(OPENFGA_CODE_KEY, "not a code".parse().unwrap());
+        assert_eq!(
+            UrkelError::from(&status),
+            UrkelError::not_found(NotFoundErrorCode::StoreIdNotFound, "boom")
+        );
+    }
+
+    #[test]
+    fn answers_deadline_exceeded_with_a_gateway_timeout() {
+        let error = UrkelError::deadline_exceeded(Duration::from_millis(250));
+        assert_eq!(error.http_status(), 504);
+        assert_eq!(
+            error.to_string(),
+            "deadline_exceeded: OpenFGA did not answer within 250 ms."
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use super::backend::{BackendError, OpenFgaBackend};
+use super::openfga::open_fga_service_client::OpenFgaServiceClient;
+use super::openfga::*;
+use super::{AuthInterceptor, Credentials, OpenFgaChannel, UrkelError};
+use crate::config::{OpenFgaConfig, TlsConfig};
+use rocket::futures::{stream::BoxStream, StreamExt};
+use std::error::Error;
+use std::time::Duration;
+use tonic::{
+    service::interceptor::InterceptedService,
+    transport::{Certificate, ClientTlsConfig, Endpoint, Identity},
+    Status,
+};
+
+type OpenFgaClient = OpenFgaServiceClient<InterceptedService<OpenFgaChannel, AuthInterceptor>>;
+
+/// Reaches OpenFGA over gRPC, through a single multiplexed channel.
+#[derive(Clone, Debug)]
+pub struct GrpcBackend {
+    service: OpenFgaClient,
+}
+
+impl GrpcBackend {
+    /// The channel connects lazily and reconnects on its own, so this does not wait for OpenFGA
+    /// to be reachable.
+    pub fn new(
+        config: &OpenFgaConfig,
+        credentials: Credentials,
+    ) -> Result<GrpcBackend, Box<dyn Error>> {
+        let mut endpoint = Endpoint::from_shared(config.api_url.clone())?
+            .connect_timeout(config.timeouts.connect());
+        if let Some(tls) = &config.tls {
+            endpoint = endpoint.tls_config(client_tls_config(tls)?)?;
+        }
+        let channel = endpoint.connect_lazy();
+
+        let service = OpenFgaServiceClient::with_interceptor(
+            OpenFgaChannel::new(channel),
+            AuthInterceptor::new(credentials),
+        );
+        Ok(GrpcBackend { service })
+    }
+}
+
+/// Reads the certificates named in the TLS configuration into tonic's client TLS settings.
+fn client_tls_config(tls: &TlsConfig) -> Result<ClientTlsConfig, std::io::Error> {
+    let mut tls_config = ClientTlsConfig::new();
+    if let Some(ca_cert_path) = &tls.ca_cert_path {
+        let ca_cert = std::fs::read(ca_cert_path)?;
+        tls_config = tls_config.ca_certificate(Certificate::from_pem(ca_cert));
+    }
+    if let (Some(cert_path), Some(key_path)) = (&tls.client_cert_path, &tls.client_key_path) {
+        let cert = std::fs::read(cert_path)?;
+        let key = std::fs::read(key_path)?;
+        tls_config = tls_config.identity(Identity::from_pem(cert, key));
+    }
+    if let Some(domain_name) = &tls.domain_name {
+        tls_config = tls_config.domain_name(domain_name);
+    }
+    Ok(tls_config)
+}
+
+/// Wraps a message in a request which tells OpenFGA when to give up on it.
+fn request<T>(message: T, timeout: Duration) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.set_timeout(timeout);
+    request
+}
+
+impl From<Status> for BackendError {
+    fn from(status: Status) -> BackendError {
+        BackendError {
+            error: UrkelError::from(&status),
+            unsent: is_connect_error(&status),
+        }
+    }
+}
+
+/// Whether the call failed because no connection to OpenFGA could be established.
+fn is_connect_error(status: &Status) -> bool {
+    let mut source = status.source();
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<hyper::Error>() {
+            if error.is_connect() {
+                return true;
+            }
+        }
+        source = error.source();
+    }
+    false
+}
+
+#[rocket::async_trait]
+impl OpenFgaBackend for GrpcBackend {
+    async fn get_store(
+        &self,
+        message: GetStoreRequest,
+        timeout: Duration,
+    ) -> Result<GetStoreResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .get_store(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn list_stores(
+        &self,
+        message: ListStoresRequest,
+        timeout: Duration,
+    ) -> Result<ListStoresResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .list_stores(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn create_store(
+        &self,
+        message: CreateStoreRequest,
+        timeout: Duration,
+    ) -> Result<CreateStoreResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .create_store(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn update_store(
+        &self,
+        message: UpdateStoreRequest,
+        timeout: Duration,
+    ) -> Result<UpdateStoreResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .update_store(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn delete_store(
+        &self,
+        message: DeleteStoreRequest,
+        timeout: Duration,
+    ) -> Result<DeleteStoreResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .delete_store(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn read_authorization_models(
+        &self,
+        message: ReadAuthorizationModelsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelsResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .read_authorization_models(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn write_authorization_model(
+        &self,
+        message: WriteAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<WriteAuthorizationModelResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .write_authorization_model(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn read_authorization_model(
+        &self,
+        message: ReadAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .read_authorization_model(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn read_changes(
+        &self,
+        message: ReadChangesRequest,
+        timeout: Duration,
+    ) -> Result<ReadChangesResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .read_changes(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn read(
+        &self,
+        message: ReadRequest,
+        timeout: Duration,
+    ) -> Result<ReadResponse, BackendError> {
+        let response = self.service.clone().read(request(message, timeout)).await?;
+        Ok(response.into_inner())
+    }
+
+    async fn write(
+        &self,
+        message: WriteRequest,
+        timeout: Duration,
+    ) -> Result<WriteResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .write(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn check(
+        &self,
+        message: CheckRequest,
+        timeout: Duration,
+    ) -> Result<CheckResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .check(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn expand(
+        &self,
+        message: ExpandRequest,
+        timeout: Duration,
+    ) -> Result<ExpandResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .expand(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn list_objects(
+        &self,
+        message: ListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<ListObjectsResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .list_objects(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn streamed_list_objects(
+        &self,
+        message: StreamedListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<BoxStream<'static, Result<StreamedListObjectsResponse, BackendError>>, BackendError>
+    {
+        let response = self
+            .service
+            .clone()
+            .streamed_list_objects(request(message, timeout))
+            .await?;
+        Ok(response
+            .into_inner()
+            .map(|result| result.map_err(BackendError::from))
+            .boxed())
+    }
+
+    async fn read_assertions(
+        &self,
+        message: ReadAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAssertionsResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .read_assertions(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn write_assertions(
+        &self,
+        message: WriteAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<WriteAssertionsResponse, BackendError> {
+        let response = self
+            .service
+            .clone()
+            .write_assertions(request(message, timeout))
+            .await?;
+        Ok(response.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder, X509};
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// A certificate for `name`, signed by `issuer` or else by itself as a certificate authority.
+    fn certificate(
+        name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(rand::random::<u16>().into()).unwrap();
+        builder
+            .set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((issuer, issuer_key)) => {
+                builder.set_issuer_name(issuer.subject_name()).unwrap();
+                let alternative_name = SubjectAlternativeName::new()
+                    .dns(name)
+                    .build(&builder.x509v3_context(Some(issuer), None))
+                    .unwrap();
+                builder.append_extension(alternative_name).unwrap();
+                builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(&subject).unwrap();
+                let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+                builder.append_extension(constraints).unwrap();
+                builder.sign(key, MessageDigest::sha256()).unwrap();
+            }
+        }
+        builder.build()
+    }
+
+    fn write_pem(name: &str, pem: Vec<u8>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("urkel-{}-{}.pem", std::process::id(), name));
+        std::fs::write(&path, pem).unwrap();
+        path
+    }
+
+    struct Pki {
+        ca: X509,
+        ca_key: PKey<Private>,
+        tls: TlsConfig,
+    }
+
+    /// A certificate authority, and a client identity it issued written out as `openfga.tls`.
+    fn pki(name: &str) -> Pki {
+        let ca_key = key();
+        let ca = certificate("Urkel Test CA", &ca_key, None);
+        let client_key = key();
+        let client = certificate("urkel", &client_key, Some((&ca, &ca_key)));
+        let tls = TlsConfig {
+            ca_cert_path: Some(write_pem(&format!("{}-ca", name), ca.to_pem().unwrap())),
+            client_cert_path: Some(write_pem(
+                &format!("{}-client", name),
+                client.to_pem().unwrap(),
+            )),
+            client_key_path: Some(write_pem(
+                &format!("{}-client-key", name),
+                client_key.private_key_to_pem_pkcs8().unwrap(),
+            )),
+            domain_name: Some("openfga.test".to_owned()),
+        };
+        Pki { ca, ca_key, tls }
+    }
+
+    fn config(api_url: String, tls: TlsConfig) -> OpenFgaConfig {
+        OpenFgaConfig {
+            api_url,
+            tls: Some(tls),
+            ..OpenFgaConfig::default()
+        }
+    }
+
+    #[rocket::async_test]
+    async fn builds_a_mutual_tls_channel() {
+        let pki = pki("builds");
+        let config = config("https://localhost:8081".to_owned(), pki.tls);
+
+        assert!(GrpcBackend::new(&config, Credentials::None).is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn fails_when_a_certificate_cannot_be_read() {
+        let mut tls = pki("unreadable").tls;
+        tls.client_key_path = Some(std::env::temp_dir().join("urkel-missing-key.pem"));
+        let config = config("https://localhost:8081".to_owned(), tls);
+
+        assert!(GrpcBackend::new(&config, Credentials::None).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn presents_the_client_identity_to_openfga() {
+        let pki = pki("handshake");
+        let server_key = key();
+        let server = certificate("openfga.test", &server_key, Some((&pki.ca, &pki.ca_key)));
+
+        // OpenFGA stand-in requiring a client certificate issued by the CA.
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&server_key).unwrap();
+        acceptor.set_certificate(&server).unwrap();
+        acceptor.cert_store_mut().add_cert(pki.ca.clone()).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        acceptor.set_alpn_select_callback(|_, _| Ok(b"h2"));
+        let acceptor = acceptor.build();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("https://{}", listener.local_addr().unwrap());
+        let (peers, peer) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let client = acceptor.accept(stream).ok().and_then(|stream| {
+                let certificate = stream.ssl().peer_certificate()?;
+                let name = certificate.subject_name().entries().next()?;
+                name.data().to_string().ok()
+            });
+            peers.send(client).unwrap();
+        });
+
+        let backend = GrpcBackend::new(&config(api_url, pki.tls), Credentials::None).unwrap();
+        // The stand-in hangs up after the handshake, so the call itself fails.
+        let _ = backend
+            .list_stores(ListStoresRequest::default(), Duration::from_secs(5))
+            .await;
+
+        let client = peer.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(client.as_deref(), Some("urkel"));
+    }
+}
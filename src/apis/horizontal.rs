@@ -0,0 +1,458 @@
+use super::{ReadUntilEndItem, UrkelError};
+use crate::models::ErrorCode;
+use rocket::futures::{
+    stream::{BoxStream, FuturesUnordered},
+    Future, StreamExt, TryStreamExt,
+};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// How many of the users found via `read_from` must have `check_for`, e.g. `"all"`, `"any"`,
+/// `{"at_least": 2}` or `{"percentage": 50}`.
+#[derive(Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum HorizontalMode {
+    #[default]
+    #[serde(rename = "all")]
+    All,
+    #[serde(rename = "any")]
+    Any,
+    /// Allows when at least this many users are allowed, and denies when there are fewer users.
+    #[serde(rename = "at_least")]
+    AtLeast(usize),
+    /// Percentage of the users, from 0 excluded to 100.
+    #[serde(rename = "percentage")]
+    Percentage(f64),
+}
+
+impl HorizontalMode {
+    /// How many of `users` must be allowed, or `None` when the mode is invalid.
+    fn threshold(self, users: usize) -> Option<usize> {
+        match self {
+            HorizontalMode::All => Some(users),
+            HorizontalMode::Any => Some(1),
+            HorizontalMode::AtLeast(n) if n > 0 => Some(n),
+            HorizontalMode::Percentage(percentage) if percentage > 0.0 && percentage <= 100.0 => {
+                Some(((users as f64) * percentage / 100.0).ceil() as usize)
+            }
+            HorizontalMode::AtLeast(_) | HorizontalMode::Percentage(_) => None,
+        }
+    }
+
+    /// The number of allowed users which allows whatever the remaining users are.
+    fn enough(self) -> Option<usize> {
+        match self {
+            HorizontalMode::Any | HorizontalMode::AtLeast(_) => self.threshold(0),
+            HorizontalMode::All | HorizontalMode::Percentage(_) => None,
+        }
+    }
+}
+
+/// The outcome of a horizontal check, with the users found via `read_from` sorted by the outcome
+/// of their check.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct CheckHorizontalResponse {
+    #[serde(rename = "allowed")]
+    pub allowed: bool,
+    #[serde(rename = "passed")]
+    pub passed: Vec<String>,
+    #[serde(rename = "failed")]
+    pub failed: Vec<String>,
+    /// Users whose check was cancelled once the outcome was known. Users which were not read by
+    /// then are not listed.
+    #[serde(rename = "skipped")]
+    pub skipped: Vec<String>,
+    /// Errors of the failed users which could not be checked.
+    #[serde(rename = "errors", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, UrkelError>,
+}
+
+/// The usersets found among the users of a relation, left to expand, and the users found so far.
+/// Each userset is only queued once.
+#[derive(Clone, Debug, Default)]
+pub struct Usersets {
+    pending: VecDeque<(String, String)>,
+    visited: HashSet<String>,
+    found: HashSet<String>,
+}
+
+impl Usersets {
+    /// The object and relation of the next userset to expand.
+    pub fn next_userset(&mut self) -> Option<(String, String)> {
+        self.pending.pop_front()
+    }
+
+    /// Queues the user of a tuple when it is a userset, or returns it when it was not found yet.
+    /// Reads cut short by the read limits fail, since their users would be missed.
+    pub fn add(&mut self, item: ReadUntilEndItem) -> Result<Option<String>, UrkelError> {
+        let user =
+            match item {
+                ReadUntilEndItem::Tuple(tuple) => tuple.key.and_then(|tuple_key| tuple_key.user),
+                ReadUntilEndItem::End(end) if end.truncated => return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Object relation from 'read_from' has more tuples than the read limits allow.",
+                )),
+                ReadUntilEndItem::End(_) => None,
+            };
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        match user.split_once('#') {
+            Some((object, relation)) => {
+                if self.visited.insert(user.clone()) {
+                    self.pending
+                        .push_back((object.to_owned(), relation.to_owned()));
+                }
+                Ok(None)
+            }
+            None => Ok(self.add_user(user)),
+        }
+    }
+
+    /// Returns a user of an expanded userset when it was not found yet.
+    pub fn add_user(&mut self, user: String) -> Option<String> {
+        Some(user).filter(|user| self.found.insert(user.clone()))
+    }
+}
+
+/// Runs `check` for the `users` as they are read, at most `max_concurrent_checks` at a time, until
+/// the outcome of `mode` is known: the first denial decides `all`, and enough allowed users decide
+/// `any` and `at_least`, without reading further. Percentages need every user. Checks failing with
+/// an error count as failed, which can only deny.
+pub async fn evaluate<F, Fut>(
+    mut users: BoxStream<'static, Result<String, UrkelError>>,
+    mode: HorizontalMode,
+    max_concurrent_checks: usize,
+    check: F,
+) -> Result<CheckHorizontalResponse, UrkelError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<bool, UrkelError>>,
+{
+    if mode.threshold(1).is_none() {
+        return Err(UrkelError::validation(
+            ErrorCode::ValidationError,
+            "Invalid mode provided.",
+        ));
+    }
+
+    let mut response = CheckHorizontalResponse::default();
+    let mut checks = FuturesUnordered::new();
+    let mut checking = Vec::new();
+    let mut read_all = false;
+    let decided = loop {
+        while !read_all && checks.len() < max_concurrent_checks.max(1) {
+            match users.try_next().await? {
+                Some(user) => {
+                    checking.push(user.clone());
+                    let check = check(user.clone());
+                    checks.push(async move { (user, check.await) });
+                }
+                None => read_all = true,
+            }
+        }
+        let (user, result) = match checks.next().await {
+            Some(outcome) => outcome,
+            None => break None,
+        };
+        checking.retain(|checked| *checked != user);
+        match result {
+            Ok(true) => response.passed.push(user),
+            Ok(false) => response.failed.push(user),
+            Err(error) => {
+                response.errors.insert(user.clone(), error);
+                response.failed.push(user);
+            }
+        }
+        if mode == HorizontalMode::All && !response.failed.is_empty() {
+            break Some(false);
+        }
+        if mode
+            .enough()
+            .is_some_and(|enough| response.passed.len() >= enough)
+        {
+            break Some(true);
+        }
+    };
+
+    response.allowed = match decided {
+        Some(allowed) => allowed,
+        None => {
+            let users = response.passed.len() + response.failed.len();
+            if users == 0 {
+                return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Object relation from 'read_from' returned no results to compare to.",
+                ));
+            }
+            mode.threshold(users)
+                .is_some_and(|threshold| response.passed.len() >= threshold)
+        }
+    };
+    response.skipped = checking;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::{Tuple, TupleKey};
+    use crate::apis::ReadEnd;
+    use crate::models::InternalErrorCode;
+    use rocket::futures::{future, stream};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Copy)]
+    enum Answer {
+        Allow,
+        Deny,
+        Fail,
+        Hang,
+    }
+
+    /// Checks the `users` as they are read, returning the response and the users checked.
+    async fn evaluate_users(
+        users: &[(&str, Answer)],
+        mode: HorizontalMode,
+        max_concurrent_checks: usize,
+    ) -> (Result<CheckHorizontalResponse, UrkelError>, Vec<String>) {
+        let names = users
+            .iter()
+            .map(|(user, _)| Ok(user.to_string()))
+            .collect::<Vec<_>>();
+        evaluate_stream(
+            stream::iter(names).boxed(),
+            users,
+            mode,
+            max_concurrent_checks,
+        )
+        .await
+    }
+
+    async fn evaluate_stream(
+        stream: BoxStream<'static, Result<String, UrkelError>>,
+        users: &[(&str, Answer)],
+        mode: HorizontalMode,
+        max_concurrent_checks: usize,
+    ) -> (Result<CheckHorizontalResponse, UrkelError>, Vec<String>) {
+        let answers = users
+            .iter()
+            .map(|(user, answer)| (user.to_string(), *answer))
+            .collect::<std::collections::HashMap<_, _>>();
+        let checked = Arc::new(Mutex::new(Vec::new()));
+        let response = evaluate(stream, mode, max_concurrent_checks, |user| {
+            checked.lock().unwrap().push(user.clone());
+            let answer = answers[&user];
+            async move {
+                match answer {
+                    Answer::Allow => Ok(true),
+                    Answer::Deny => Ok(false),
+                    Answer::Fail => Err(UrkelError::internal(
+                        InternalErrorCode::Unavailable,
+                        "OpenFGA is unavailable.",
+                    )),
+                    Answer::Hang => future::pending().await,
+                }
+            }
+        })
+        .await;
+        let checked = checked.lock().unwrap().clone();
+        (response, checked)
+    }
+
+    async fn allowed(users: &[(&str, Answer)], mode: HorizontalMode) -> bool {
+        evaluate_users(users, mode, 2).await.0.unwrap().allowed
+    }
+
+    #[test]
+    fn reads_modes_by_name() {
+        let modes: Vec<HorizontalMode> =
+            serde_json::from_str(r#"["all", "any", {"at_least": 2}, {"percentage": 50}]"#).unwrap();
+
+        assert_eq!(
+            modes,
+            vec![
+                HorizontalMode::All,
+                HorizontalMode::Any,
+                HorizontalMode::AtLeast(2),
+                HorizontalMode::Percentage(50.0),
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn allows_when_all_users_are_allowed() {
+        use Answer::*;
+        assert!(allowed(&[("user:a", Allow), ("user:b", Allow)], HorizontalMode::All).await);
+        assert!(!allowed(&[("user:a", Allow), ("user:b", Deny)], HorizontalMode::All).await);
+    }
+
+    #[rocket::async_test]
+    async fn allows_when_any_user_is_allowed() {
+        use Answer::*;
+        assert!(allowed(&[("user:a", Deny), ("user:b", Allow)], HorizontalMode::Any).await);
+        assert!(!allowed(&[("user:a", Deny), ("user:b", Deny)], HorizontalMode::Any).await);
+    }
+
+    #[rocket::async_test]
+    async fn allows_when_at_least_n_users_are_allowed() {
+        use Answer::*;
+        let users = [("user:a", Allow), ("user:b", Deny), ("user:c", Allow)];
+
+        assert!(allowed(&users, HorizontalMode::AtLeast(2)).await);
+        // Denies when there are fewer users than needed.
+        assert!(!allowed(&users[..2], HorizontalMode::AtLeast(3)).await);
+    }
+
+    #[rocket::async_test]
+    async fn allows_when_a_percentage_of_users_is_allowed() {
+        use Answer::*;
+        let users = [
+            ("user:a", Allow),
+            ("user:b", Deny),
+            ("user:c", Allow),
+            ("user:d", Allow),
+        ];
+
+        assert!(allowed(&users, HorizontalMode::Percentage(75.0)).await);
+        assert!(!allowed(&users, HorizontalMode::Percentage(80.0)).await);
+        // Rounds the number of users needed up.
+        assert!(allowed(&users[..3], HorizontalMode::Percentage(66.0)).await);
+        assert!(!allowed(&users[..3], HorizontalMode::Percentage(67.0)).await);
+        assert!(allowed(&users, HorizontalMode::Percentage(100.0 / 3.0)).await);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_modes() {
+        use Answer::*;
+        for mode in [
+            HorizontalMode::AtLeast(0),
+            HorizontalMode::Percentage(0.0),
+            HorizontalMode::Percentage(100.5),
+            HorizontalMode::Percentage(f64::NAN),
+        ] {
+            let (response, checked) = evaluate_users(&[("user:a", Allow)], mode, 1).await;
+            assert!(
+                matches!(response, Err(UrkelError::Validation(_))),
+                "{:?}",
+                mode
+            );
+            assert!(checked.is_empty());
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_relations_without_users() {
+        let (response, _) = evaluate_users(&[], HorizontalMode::Any, 1).await;
+
+        assert!(matches!(response, Err(UrkelError::Validation(_))));
+    }
+
+    #[rocket::async_test]
+    async fn reports_users_by_outcome() {
+        use Answer::*;
+        let users = [("user:a", Allow), ("user:b", Fail), ("user:c", Deny)];
+
+        let (response, _) = evaluate_users(&users, HorizontalMode::Percentage(50.0), 1).await;
+
+        let response = response.unwrap();
+        assert!(!response.allowed);
+        assert_eq!(response.passed, vec!["user:a"]);
+        assert_eq!(response.failed, vec!["user:b", "user:c"]);
+        assert!(response.skipped.is_empty());
+        assert_eq!(response.errors.keys().collect::<Vec<_>>(), vec!["user:b"]);
+    }
+
+    #[rocket::async_test]
+    async fn stops_reading_at_the_first_denial() {
+        use Answer::*;
+        let users = [("user:a", Deny), ("user:b", Allow), ("user:c", Allow)];
+
+        let (response, checked) = evaluate_users(&users, HorizontalMode::All, 1).await;
+
+        let response = response.unwrap();
+        assert!(!response.allowed);
+        assert_eq!(response.failed, vec!["user:a"]);
+        assert_eq!(checked, vec!["user:a"]);
+    }
+
+    #[rocket::async_test]
+    async fn stops_reading_once_enough_users_are_allowed() {
+        let users = stream::iter(vec![Ok("user:a".to_owned())])
+            .chain(stream::pending())
+            .boxed();
+
+        let (response, checked) =
+            evaluate_stream(users, &[("user:a", Answer::Allow)], HorizontalMode::Any, 1).await;
+
+        assert!(response.unwrap().allowed);
+        assert_eq!(checked, vec!["user:a"]);
+    }
+
+    #[rocket::async_test]
+    async fn reports_checks_in_flight_as_skipped() {
+        use Answer::*;
+        let users = [
+            ("user:a", Hang),
+            ("user:b", Allow),
+            ("user:c", Hang),
+            ("user:d", Allow),
+        ];
+
+        let (response, checked) = evaluate_users(&users, HorizontalMode::AtLeast(2), 4).await;
+
+        let response = response.unwrap();
+        assert!(response.allowed);
+        assert_eq!(response.passed, vec!["user:b", "user:d"]);
+        assert_eq!(response.skipped, vec!["user:a", "user:c"]);
+        assert_eq!(checked.len(), 4);
+    }
+
+    fn tuple(user: &str) -> ReadUntilEndItem {
+        ReadUntilEndItem::Tuple(Tuple {
+            key: Some(TupleKey {
+                object: Some("team:core".to_owned()),
+                relation: Some("member".to_owned()),
+                user: Some(user.to_owned()),
+            }),
+            timestamp: None,
+        })
+    }
+
+    #[test]
+    fn queues_each_userset_once_and_finds_each_user_once() {
+        let mut usersets = Usersets::default();
+
+        assert_eq!(usersets.add(tuple("user:a")), Ok(Some("user:a".to_owned())));
+        assert_eq!(usersets.add(tuple("group:eng#member")), Ok(None));
+        assert_eq!(usersets.add(tuple("group:eng#member")), Ok(None));
+        assert_eq!(usersets.add(tuple("user:a")), Ok(None));
+        assert_eq!(usersets.add(tuple("user:*")), Ok(Some("user:*".to_owned())));
+        assert_eq!(
+            usersets.add(ReadUntilEndItem::End(ReadEnd::default())),
+            Ok(None)
+        );
+
+        assert_eq!(
+            usersets.next_userset(),
+            Some(("group:eng".to_owned(), "member".to_owned()))
+        );
+        assert_eq!(usersets.next_userset(), None);
+        assert_eq!(usersets.add_user("user:a".to_owned()), None);
+        assert_eq!(
+            usersets.add_user("user:b".to_owned()),
+            Some("user:b".to_owned())
+        );
+    }
+
+    #[test]
+    fn fails_on_truncated_reads() {
+        let mut usersets = Usersets::default();
+
+        let added = usersets.add(ReadUntilEndItem::End(ReadEnd {
+            continuation_token: "token".to_owned(),
+            truncated: true,
+        }));
+
+        assert!(matches!(added, Err(UrkelError::Validation(_))));
+    }
+}
@@ -0,0 +1,597 @@
+use super::openfga::userset_tree::{leaf, node, Node};
+use super::openfga::UsersetTree;
+use super::UrkelError;
+use crate::models::ErrorCode;
+use rocket::futures::{future::BoxFuture, Future, FutureExt};
+use std::collections::{BTreeSet, HashMap};
+
+/// The users having a relation with an object, sorted. Public access is listed as the wildcard of
+/// its type, e.g. `user:*`.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ListUsersResponse {
+    #[serde(rename = "users")]
+    pub users: Vec<String>,
+    /// Users of the listed wildcards' types who are nonetheless excluded, e.g. by a `but not`.
+    #[serde(rename = "excluded_users", default)]
+    pub excluded_users: Vec<String>,
+}
+
+/// Users and wildcards, the types whose every user is included but the `excluded` ones.
+#[derive(Clone, Debug, Default)]
+struct Users {
+    users: BTreeSet<String>,
+    wildcards: BTreeSet<String>,
+    excluded: BTreeSet<String>,
+}
+
+fn type_of(user: &str) -> &str {
+    user.split_once(':')
+        .map_or(user, |(user_type, _)| user_type)
+}
+
+impl Users {
+    fn insert(&mut self, user: String) {
+        match user.strip_suffix(":*") {
+            Some(user_type) => {
+                self.excluded
+                    .retain(|excluded| type_of(excluded) != user_type);
+                self.wildcards.insert(user_type.to_owned());
+            }
+            None => {
+                self.excluded.remove(&user);
+                self.users.insert(user);
+            }
+        };
+    }
+
+    fn contains(&self, user: &str) -> bool {
+        self.users.contains(user)
+            || (self.wildcards.contains(type_of(user)) && !self.excluded.contains(user))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.wildcards.is_empty()
+    }
+
+    fn union(mut self, other: Users) -> Users {
+        let excluded = self
+            .excluded
+            .iter()
+            .chain(&other.excluded)
+            .filter(|user| !self.contains(user) && !other.contains(user))
+            .cloned()
+            .collect();
+        self.users.extend(other.users);
+        self.wildcards.extend(other.wildcards);
+        self.excluded = excluded;
+        self
+    }
+
+    fn intersection(self, other: Users) -> Users {
+        let users = self
+            .users
+            .iter()
+            .filter(|user| other.contains(user))
+            .chain(other.users.iter().filter(|user| self.contains(user)))
+            .cloned()
+            .collect();
+        let wildcards: BTreeSet<String> = self
+            .wildcards
+            .intersection(&other.wildcards)
+            .cloned()
+            .collect();
+        let excluded = self
+            .excluded
+            .union(&other.excluded)
+            .filter(|user| wildcards.contains(type_of(user)))
+            .cloned()
+            .collect();
+        Users {
+            users,
+            wildcards,
+            excluded,
+        }
+    }
+
+    /// A wildcard stays listed when only some users of its type are subtracted, which are then
+    /// excluded from it.
+    fn difference(self, other: Users) -> Users {
+        let users = self
+            .users
+            .iter()
+            .filter(|user| !other.contains(user))
+            .chain(other.excluded.iter().filter(|user| self.contains(user)))
+            .cloned()
+            .collect();
+        let wildcards: BTreeSet<String> = &self.wildcards - &other.wildcards;
+        let excluded = self
+            .excluded
+            .iter()
+            .chain(&other.users)
+            .filter(|user| wildcards.contains(type_of(user)))
+            .cloned()
+            .collect();
+        Users {
+            users,
+            wildcards,
+            excluded,
+        }
+    }
+}
+
+/// The recursive expansion of usersets, expanding each userset met once, even across the usersets
+/// resolved with the same expansion. Within a cycle, only the userset reached first lists the users
+/// of the whole cycle; the others miss those of the usersets expanded before them, so they are not
+/// kept and are expanded again when met elsewhere.
+pub struct Expansion<F> {
+    expand: F,
+    resolved: HashMap<String, Users>,
+    /// Usersets being expanded, outermost first. Meeting one again closes a cycle, which is cut
+    /// by counting it as empty there.
+    expanding: Vec<String>,
+    /// The outermost position in `expanding` of the usersets cut within the current expansion.
+    cut: Option<usize>,
+}
+
+impl<F, Fut> Expansion<F>
+where
+    F: Fn(String, String) -> Fut + Send,
+    Fut: Future<Output = Result<UsersetTree, UrkelError>> + Send,
+{
+    /// An expansion calling `expand` with the object and relation of each userset to expand.
+    pub fn new(expand: F) -> Expansion<F> {
+        Expansion {
+            expand,
+            resolved: HashMap::new(),
+            expanding: Vec::new(),
+            cut: None,
+        }
+    }
+
+    /// The users of `object#relation`, sorted.
+    pub async fn users(
+        &mut self,
+        object: &str,
+        relation: &str,
+    ) -> Result<ListUsersResponse, UrkelError> {
+        let found = self.userset(&format!("{}#{}", object, relation)).await?;
+        let wildcards = found
+            .wildcards
+            .into_iter()
+            .map(|wildcard| format!("{}:*", wildcard));
+        let mut users: Vec<String> = found.users.into_iter().chain(wildcards).collect();
+        users.sort();
+        Ok(ListUsersResponse {
+            users,
+            excluded_users: found.excluded.into_iter().collect(),
+        })
+    }
+
+    fn userset<'a>(&'a mut self, userset: &'a str) -> BoxFuture<'a, Result<Users, UrkelError>> {
+        async move {
+            if let Some(users) = self.resolved.get(userset) {
+                return Ok(users.clone());
+            }
+            let (object, relation) = userset.split_once('#').ok_or_else(|| {
+                UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    format!("Userset '{}' names no relation.", userset),
+                )
+            })?;
+            if let Some(depth) = self.expanding.iter().position(|other| other == userset) {
+                self.cut = Some(self.cut.map_or(depth, |cut| cut.min(depth)));
+                return Ok(Users::default());
+            }
+
+            let depth = self.expanding.len();
+            self.expanding.push(userset.to_owned());
+            let outer_cut = self.cut.take();
+            let tree = (self.expand)(object.to_owned(), relation.to_owned()).await?;
+            let users = self.node(tree.root.unwrap_or_default()).await?;
+            self.expanding.pop();
+
+            // Only cuts of the usersets expanded before this one leave it incomplete.
+            let cut = self.cut.filter(|cut| *cut < depth);
+            if cut.is_none() {
+                self.resolved.insert(userset.to_owned(), users.clone());
+            }
+            self.cut = outer_cut.into_iter().chain(cut).min();
+            Ok(users)
+        }
+        .boxed()
+    }
+
+    fn node(&mut self, node: Node) -> BoxFuture<'_, Result<Users, UrkelError>> {
+        async move {
+            let users = match node.value {
+                None => Users::default(),
+                Some(node::Value::Leaf(leaf)) => match leaf.value {
+                    None => Users::default(),
+                    Some(leaf::Value::Users(leaf_users)) => {
+                        let mut users = Users::default();
+                        for user in leaf_users.users {
+                            if user.contains('#') {
+                                users = users.union(self.userset(&user).await?);
+                            } else {
+                                users.insert(user);
+                            }
+                        }
+                        users
+                    }
+                    Some(leaf::Value::Computed(computed)) => {
+                        self.userset(&computed.userset).await?
+                    }
+                    Some(leaf::Value::TupleToUserset(tuple_to_userset)) => {
+                        let mut users = Users::default();
+                        for computed in tuple_to_userset.computed {
+                            users = users.union(self.userset(&computed.userset).await?);
+                        }
+                        users
+                    }
+                },
+                Some(node::Value::Union(nodes)) => {
+                    let mut users = Users::default();
+                    for node in nodes.nodes {
+                        users = users.union(self.node(node).await?);
+                    }
+                    users
+                }
+                Some(node::Value::Intersection(nodes)) => {
+                    let mut nodes = nodes.nodes.into_iter();
+                    let mut users = match nodes.next() {
+                        Some(node) => self.node(node).await?,
+                        None => Users::default(),
+                    };
+                    for node in nodes {
+                        if users.is_empty() {
+                            break;
+                        }
+                        users = users.intersection(self.node(node).await?);
+                    }
+                    users
+                }
+                Some(node::Value::Difference(difference)) => {
+                    let base = self.node(*difference.base.unwrap_or_default()).await?;
+                    if base.is_empty() {
+                        base
+                    } else {
+                        // A cut within the subtracted usersets would subtract too few users.
+                        let depth = self.expanding.len();
+                        let outer_cut = self.cut.take();
+                        let subtract = self.node(*difference.subtract.unwrap_or_default()).await?;
+                        if let Some(cut) = self.cut.filter(|cut| *cut < depth) {
+                            return Err(UrkelError::validation(
+                                ErrorCode::AuthorizationModelResolutionTooComplex,
+                                format!(
+                                    "The users of '{}' cannot be listed, as they are excluded \
+                                     from themselves through a cycle.",
+                                    self.expanding[cut]
+                                ),
+                            ));
+                        }
+                        self.cut = outer_cut;
+                        base.difference(subtract)
+                    }
+                }
+            };
+            Ok(users)
+        }
+        .boxed()
+    }
+}
+
+/// Lists the users of `object#relation` by calling `expand` for it, then for every computed
+/// userset, tuple-to-userset and group userset of the trees, one after the other. Unions,
+/// intersections and differences are applied as in the authorization model, and cycles between
+/// usersets are cut. Users are only listed when of `user_type`, if any.
+pub async fn resolve<F, Fut>(
+    object: &str,
+    relation: &str,
+    user_type: Option<&str>,
+    expand: F,
+) -> Result<ListUsersResponse, UrkelError>
+where
+    F: Fn(String, String) -> Fut + Send,
+    Fut: Future<Output = Result<UsersetTree, UrkelError>> + Send,
+{
+    let mut response = Expansion::new(expand).users(object, relation).await?;
+    if let Some(wanted) = user_type {
+        response.users.retain(|user| type_of(user) == wanted);
+        response
+            .excluded_users
+            .retain(|user| type_of(user) == wanted);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::userset_tree::{
+        self, Computed, Difference, Leaf, Nodes, TupleToUserset,
+    };
+    use crate::models::InternalErrorCode;
+    use std::sync::{Arc, Mutex};
+
+    fn leaf(value: leaf::Value) -> Node {
+        Node {
+            name: String::new(),
+            value: Some(node::Value::Leaf(Leaf { value: Some(value) })),
+        }
+    }
+
+    fn users(users: &[&str]) -> Node {
+        leaf(leaf::Value::Users(userset_tree::Users {
+            users: users.iter().map(|user| user.to_string()).collect(),
+        }))
+    }
+
+    fn computed(userset: &str) -> Node {
+        leaf(leaf::Value::Computed(Computed {
+            userset: userset.to_owned(),
+        }))
+    }
+
+    fn union(nodes: Vec<Node>) -> Node {
+        Node {
+            name: String::new(),
+            value: Some(node::Value::Union(Nodes { nodes })),
+        }
+    }
+
+    fn intersection(nodes: Vec<Node>) -> Node {
+        Node {
+            name: String::new(),
+            value: Some(node::Value::Intersection(Nodes { nodes })),
+        }
+    }
+
+    fn difference(base: Node, subtract: Node) -> Node {
+        Node {
+            name: String::new(),
+            value: Some(node::Value::Difference(Box::new(Difference {
+                base: Some(Box::new(base)),
+                subtract: Some(Box::new(subtract)),
+            }))),
+        }
+    }
+
+    /// Resolves `doc:1#viewer` with the `trees` of the usersets, returning the users listed and
+    /// the usersets expanded.
+    async fn resolve_trees(
+        trees: Vec<(&str, Node)>,
+        user_type: Option<&str>,
+    ) -> (Result<ListUsersResponse, UrkelError>, Vec<String>) {
+        let trees = trees
+            .into_iter()
+            .map(|(userset, node)| (userset.to_owned(), node))
+            .collect::<HashMap<_, _>>();
+        let expanded = Arc::new(Mutex::new(Vec::new()));
+        let calls = expanded.clone();
+        let response = resolve("doc:1", "viewer", user_type, move |object, relation| {
+            let userset = format!("{}#{}", object, relation);
+            calls.lock().unwrap().push(userset.clone());
+            let tree = match trees.get(&userset) {
+                Some(node) => Ok(UsersetTree {
+                    root: Some(node.clone()),
+                }),
+                None => Err(UrkelError::internal(
+                    InternalErrorCode::Unavailable,
+                    "OpenFGA is unavailable.",
+                )),
+            };
+            async move { tree }
+        })
+        .await;
+        let expanded = expanded.lock().unwrap().clone();
+        (response, expanded)
+    }
+
+    #[rocket::async_test]
+    async fn applies_unions_intersections_and_differences() {
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                difference(
+                    intersection(vec![
+                        computed("doc:1#editor"),
+                        users(&["user:a", "user:b", "user:c", "employee:e"]),
+                    ]),
+                    users(&["user:b"]),
+                ),
+            ),
+            (
+                "doc:1#editor",
+                union(vec![
+                    users(&["user:a", "user:b"]),
+                    computed("team:t#member"),
+                ]),
+            ),
+            ("team:t#member", users(&["user:d", "employee:*"])),
+        ];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        assert_eq!(users.unwrap().users, vec!["employee:e", "user:a"]);
+    }
+
+    #[rocket::async_test]
+    async fn excludes_from_wildcards_the_users_subtracted() {
+        let trees = vec![(
+            "doc:1#viewer",
+            difference(users(&["user:*", "user:a"]), users(&["user:b"])),
+        )];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        assert_eq!(
+            users.unwrap(),
+            ListUsersResponse {
+                users: vec!["user:*".to_owned(), "user:a".to_owned()],
+                excluded_users: vec!["user:b".to_owned()],
+            }
+        );
+    }
+
+    #[rocket::async_test]
+    async fn combines_the_users_excluded_from_wildcards() {
+        // Anyone but b, c and d, which is not blocked, or e, who is.
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                union(vec![
+                    computed("doc:1#public"),
+                    difference(users(&["user:d", "user:e"]), users(&["user:e"])),
+                ]),
+            ),
+            (
+                "doc:1#public",
+                difference(
+                    difference(users(&["user:*"]), users(&["user:b", "user:c"])),
+                    difference(users(&["user:d", "user:e"]), users(&["user:d"])),
+                ),
+            ),
+        ];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        let users = users.unwrap();
+        assert_eq!(users.users, vec!["user:*", "user:d"]);
+        assert_eq!(users.excluded_users, vec!["user:b", "user:c", "user:e"]);
+    }
+
+    #[rocket::async_test]
+    async fn expands_group_usersets_and_tuple_to_usersets() {
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                union(vec![
+                    users(&["user:a", "group:g#member"]),
+                    leaf(leaf::Value::TupleToUserset(TupleToUserset {
+                        tupleset: "doc:1#parent".to_owned(),
+                        computed: vec![Computed {
+                            userset: "folder:f#viewer".to_owned(),
+                        }],
+                    })),
+                ]),
+            ),
+            ("group:g#member", users(&["user:b", "employee:c"])),
+            ("folder:f#viewer", users(&["user:f"])),
+        ];
+
+        let (users, _) = resolve_trees(trees, Some("user")).await;
+
+        assert_eq!(users.unwrap().users, vec!["user:a", "user:b", "user:f"]);
+    }
+
+    #[rocket::async_test]
+    async fn expands_each_userset_once() {
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                union(vec![computed("doc:1#editor"), computed("doc:1#owner")]),
+            ),
+            ("doc:1#editor", computed("team:t#member")),
+            ("doc:1#owner", computed("team:t#member")),
+            ("team:t#member", users(&["user:t"])),
+        ];
+
+        let (users, expanded) = resolve_trees(trees, None).await;
+
+        assert_eq!(users.unwrap().users, vec!["user:t"]);
+        assert_eq!(
+            expanded,
+            vec![
+                "doc:1#viewer",
+                "doc:1#editor",
+                "team:t#member",
+                "doc:1#owner"
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn lists_the_users_of_a_whole_cycle() {
+        let trees = vec![
+            ("doc:1#viewer", computed("group:x#member")),
+            (
+                "group:x#member",
+                union(vec![users(&["user:b"]), computed("group:y#member")]),
+            ),
+            (
+                "group:y#member",
+                union(vec![users(&["user:c"]), computed("group:x#member")]),
+            ),
+        ];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        assert_eq!(users.unwrap().users, vec!["user:b", "user:c"]);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_reuse_usersets_cut_short_by_a_cycle() {
+        // Members of x but not of y, which have the same members.
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                difference(computed("group:x#member"), computed("group:y#member")),
+            ),
+            (
+                "group:x#member",
+                union(vec![users(&["user:b"]), computed("group:y#member")]),
+            ),
+            (
+                "group:y#member",
+                union(vec![users(&["user:c"]), computed("group:x#member")]),
+            ),
+        ];
+
+        let (users, expanded) = resolve_trees(trees, None).await;
+
+        assert_eq!(users.unwrap().users, Vec::<String>::new());
+        assert_eq!(
+            expanded,
+            vec![
+                "doc:1#viewer",
+                "group:x#member",
+                "group:y#member",
+                "group:y#member"
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn fails_when_a_userset_cannot_be_expanded() {
+        let trees = vec![("doc:1#viewer", computed("team:t#member"))];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        assert!(matches!(users, Err(UrkelError::Internal(_))));
+    }
+
+    #[rocket::async_test]
+    async fn fails_when_a_cycle_is_cut_within_a_subtraction() {
+        // Anyone but the blocked users, who include the viewers of the document.
+        let trees = vec![
+            (
+                "doc:1#viewer",
+                difference(users(&["user:*"]), computed("doc:1#blocked")),
+            ),
+            (
+                "doc:1#blocked",
+                union(vec![users(&["user:b"]), computed("doc:1#viewer")]),
+            ),
+        ];
+
+        let (users, _) = resolve_trees(trees, None).await;
+
+        match users {
+            Err(UrkelError::Validation(error)) => assert_eq!(
+                error.code,
+                Some(ErrorCode::AuthorizationModelResolutionTooComplex)
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,20 +1,119 @@
-use rocket::futures::{stream, StreamExt};
-use std::env;
+use crate::config::{CredentialsConfig, CredentialsMethod, OpenFgaConfig, Operation, Transport};
+use crate::models::{ErrorCode, InternalErrorCode};
+use prost::Message;
+use rocket::futures::{
+    future::BoxFuture,
+    stream::{self, BoxStream},
+    FutureExt, StreamExt, TryStreamExt,
+};
+use rocket::tokio::sync::Semaphore;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // gRPC
 pub mod openfga {
+    #![allow(
+        non_local_definitions,
+        clippy::doc_overindented_list_items,
+        clippy::reserve_after_initialization
+    )]
     tonic::include_proto!("openfga.v1");
 }
-use open_fga_service_client::OpenFgaServiceClient;
 use openfga::*;
 
-use tonic::{
-    metadata::MetadataValue, service::interceptor::InterceptedService, transport::Channel, Request,
-    Status,
-};
+pub mod backend;
+pub use self::backend::{BackendError, OpenFgaBackend};
+pub mod cache;
+pub use self::cache::DecisionCache;
+pub mod channel;
+pub use self::channel::OpenFgaChannel;
+pub mod coalesce;
+pub use self::coalesce::{CheckCoalescer, CheckMetrics};
+pub mod credentials;
+pub use self::credentials::{AuthInterceptor, ClientCredentials, Credentials};
+pub mod error;
+pub use self::error::UrkelError;
+pub mod grpc;
+pub use self::grpc::GrpcBackend;
+pub mod horizontal;
+pub use self::horizontal::{CheckHorizontalResponse, HorizontalMode};
+pub mod list_users;
+pub use self::list_users::ListUsersResponse;
+pub mod pagination;
+pub use self::pagination::Page;
+pub mod rest;
+pub use self::rest::RestBackend;
+pub mod retry;
+pub use self::retry::{Idempotency, RetryPolicy};
+pub mod threshold;
+pub use self::threshold::ThresholdCheckResponse;
+pub mod watcher;
+pub use self::watcher::{ChangeWatcher, StoreChange};
+pub mod webhooks;
+pub use self::webhooks::{WebhookDispatcher, WebhookEvent, WebhookStatus};
+
+/// A check of a batch, with an optional id echoed in its result.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct BatchCheckItem {
+    #[serde(rename = "correlation_id", skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    pub request: CheckRequest,
+}
+
+impl From<CheckRequest> for BatchCheckItem {
+    fn from(request: CheckRequest) -> BatchCheckItem {
+        BatchCheckItem {
+            correlation_id: None,
+            request,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct BatchCheckRequest {
+    #[serde(rename = "checks")]
+    pub checks: Vec<BatchCheckItem>,
+    /// Lowers the configured `max_concurrent_checks` for this batch, which also caps it.
+    #[serde(
+        rename = "max_concurrent_checks",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_concurrent_checks: Option<usize>,
+}
+
+impl BatchCheckRequest {
+    pub fn new(checks: Vec<BatchCheckItem>) -> BatchCheckRequest {
+        BatchCheckRequest {
+            checks,
+            max_concurrent_checks: None,
+        }
+    }
+}
+
+/// A batch check body, either its checks alone or a `BatchCheckRequest`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BatchCheckBody {
+    Checks(Vec<BatchCheckItem>),
+    Request(BatchCheckRequest),
+}
+
+impl From<BatchCheckBody> for BatchCheckRequest {
+    fn from(body: BatchCheckBody) -> BatchCheckRequest {
+        match body {
+            BatchCheckBody::Checks(checks) => BatchCheckRequest::new(checks),
+            BatchCheckBody::Request(request) => request,
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct BatchCheckResponse {
+    #[serde(rename = "correlation_id", skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
     #[serde(rename = "allowed", skip_serializing_if = "Option::is_none")]
     pub allowed: Option<bool>,
     #[serde(rename = "_request", skip_serializing_if = "Option::is_none")]
@@ -26,6 +125,7 @@ pub struct BatchCheckResponse {
 impl BatchCheckResponse {
     pub fn new() -> BatchCheckResponse {
         BatchCheckResponse {
+            correlation_id: None,
             allowed: None,
             request: None,
             err: None,
@@ -43,9 +143,88 @@ pub struct CheckNOfMRequest {
 
 impl CheckNOfMRequest {
     pub fn new(checks: Vec<CheckRequest>, num: usize) -> CheckNOfMRequest {
-        CheckNOfMRequest {
-            checks: checks,
-            num: num,
+        CheckNOfMRequest { checks, num }
+    }
+}
+
+/// A check counting for `weight`, like the voting power of a member.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct WeightedCheck {
+    #[serde(rename = "weight")]
+    pub weight: u64,
+    #[serde(flatten)]
+    pub request: CheckRequest,
+}
+
+/// The weight the allowed checks must reach.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WeightThreshold {
+    #[serde(rename = "weight")]
+    Weight(u64),
+    /// Percentage of the total weight of the checks, from 0 excluded to 100.
+    #[serde(rename = "percentage")]
+    Percentage(f64),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CheckWeightedRequest {
+    #[serde(rename = "checks")]
+    pub checks: Vec<WeightedCheck>,
+    #[serde(rename = "threshold")]
+    pub threshold: WeightThreshold,
+}
+
+impl CheckWeightedRequest {
+    pub fn new(checks: Vec<WeightedCheck>, threshold: WeightThreshold) -> CheckWeightedRequest {
+        CheckWeightedRequest { checks, threshold }
+    }
+}
+
+/// A boolean combination of checks, e.g.
+/// `{"all": [{"check": ...}, {"any": [{"check": ...}, {"at_least": {"n": 2, "of": [...]}}]}]}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PolicyExpression {
+    #[serde(rename = "check")]
+    Check(CheckRequest),
+    #[serde(rename = "all")]
+    All(Vec<PolicyExpression>),
+    #[serde(rename = "any")]
+    Any(Vec<PolicyExpression>),
+    #[serde(rename = "not")]
+    Not(Box<PolicyExpression>),
+    #[serde(rename = "at_least")]
+    AtLeast {
+        #[serde(rename = "n")]
+        num: usize,
+        #[serde(rename = "of")]
+        of: Vec<PolicyExpression>,
+    },
+}
+
+impl PolicyExpression {
+    fn validate(&self) -> Result<(), UrkelError> {
+        match self {
+            PolicyExpression::Check(_) => Ok(()),
+            PolicyExpression::Not(expression) => expression.validate(),
+            PolicyExpression::All(expressions) | PolicyExpression::Any(expressions) => {
+                // An empty 'all' would always allow, and an empty 'any' never.
+                if expressions.is_empty() {
+                    return Err(UrkelError::validation(
+                        ErrorCode::ValidationError,
+                        "'all' and 'any' need at least one expression.",
+                    ));
+                }
+                expressions.iter().try_for_each(PolicyExpression::validate)
+            }
+            PolicyExpression::AtLeast { num, of } => {
+                if *num == 0 || *num > of.len() {
+                    return Err(UrkelError::validation(
+                        ErrorCode::ValidationError,
+                        "Invalid n value provided in 'at_least'.",
+                    ));
+                }
+                of.iter().try_for_each(PolicyExpression::validate)
+            }
         }
     }
 }
@@ -61,6 +240,11 @@ pub struct CheckHorizontalRequest {
         skip_serializing_if = "Option::is_none"
     )]
     pub authorization_model_id: Option<String>,
+    #[serde(rename = "mode", default)]
+    pub mode: HorizontalMode,
+    /// Page size of the reads of `read_from` and of the usersets found there.
+    #[serde(rename = "page_size", skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<i32>,
 }
 
 impl CheckHorizontalRequest {
@@ -69,459 +253,1812 @@ impl CheckHorizontalRequest {
             read_from: Box::new(read_from),
             check_for: Box::new(check_for),
             authorization_model_id: None,
+            mode: HorizontalMode::default(),
+            page_size: None,
         }
     }
 }
 
-const CONCURRENT_REQUESTS: usize = 2;
-
-pub async fn get_default_client() -> Result<
-    OpenFgaServiceClient<
-        InterceptedService<Channel, impl Fn(Request<()>) -> Result<Request<()>, Status>>,
-    >,
-    Box<dyn std::error::Error>,
-> {
-    let token = env::var("OPENFGA_BEARER_TOKEN").map_err(|_| {
-        "Pass a valid preshared token via `OPENFGA_BEARER_TOKEN` environment variable.".to_string()
-    })?;
-    let mut default_base_path = "grpc://[::1]:8081".to_owned();
+/// A lookup of the users having `relation` with `object`.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ListUsersRequest {
+    #[serde(rename = "object")]
+    pub object: String,
+    #[serde(rename = "relation")]
+    pub relation: String,
+    /// Only lists the users of this type, e.g. `"user"`.
+    #[serde(rename = "user_type", skip_serializing_if = "Option::is_none")]
+    pub user_type: Option<String>,
+    #[serde(
+        rename = "authorization_model_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub authorization_model_id: Option<String>,
+}
 
-    if let Ok(fga_addr) = env::var("OPENFGA_ADDR") {
-        default_base_path = fga_addr.clone();
-    }
+/// Caps on the tuples gathered by `read_until_end`. The configured read limits still apply when
+/// they are higher or unset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ReadLimits {
+    #[serde(rename = "max_tuples", skip_serializing_if = "Option::is_none")]
+    pub max_tuples: Option<usize>,
+    #[serde(rename = "max_pages", skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<usize>,
+}
 
-    let channel = Channel::from_shared(default_base_path)?.connect().await?;
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ReadUntilEndResponse {
+    #[serde(rename = "tuples")]
+    pub tuples: Vec<Tuple>,
+    #[serde(flatten)]
+    pub end: ReadEnd,
+}
 
-    let bearer_token = format!("Bearer {}", token);
-    let header_value: MetadataValue<_> = bearer_token.parse()?;
+/// Where a read following continuation tokens stopped.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ReadEnd {
+    /// Resumes the read after the last tuple returned. Empty once every tuple was read.
+    #[serde(rename = "continuation_token")]
+    pub continuation_token: String,
+    /// Set when a limit stopped the read before its end.
+    #[serde(rename = "truncated")]
+    pub truncated: bool,
+}
 
-    let service = OpenFgaServiceClient::with_interceptor(channel, move |mut req: Request<()>| {
-        req.metadata_mut()
-            .insert("authorization", header_value.clone());
-        Ok(req)
-    });
-    Ok(service)
+/// An item of `read_until_end_stream`: each tuple, then where the read stopped.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReadUntilEndItem {
+    #[serde(rename = "tuple")]
+    Tuple(Tuple),
+    #[serde(rename = "end")]
+    End(ReadEnd),
 }
 
-pub async fn get_store(
-    store_id: &str,
-) -> Result<tonic::Response<GetStoreResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+/// A page of tuples read by `read_pages`, or where the read stopped after the last one.
+enum ReadPage {
+    Tuples(ReadResponse),
+    End(ReadEnd),
+}
 
-    let request = tonic::Request::new(GetStoreRequest {
-        store_id: store_id.into(),
-    });
+/// Progress of `read_pages`.
+enum ReadState {
+    Reading {
+        continuation_token: String,
+        tuples: usize,
+        pages: usize,
+    },
+    Ended(ReadEnd),
+    Done,
+}
 
-    let response = client.get_store(request).await?;
-    Ok(response)
+/// Page size used by OpenFGA when a read does not set one.
+const DEFAULT_READ_PAGE_SIZE: i32 = 50;
+const DEFAULT_HORIZONTAL_PAGE_SIZE: i32 = 100;
+
+/// A long-lived OpenFGA client. Its backend holds the connections shared by every call, so it is
+/// cheap to clone and meant to be built once per process.
+#[derive(Clone, Debug)]
+pub struct UrkelClient {
+    backend: Arc<dyn OpenFgaBackend>,
+    credentials: Credentials,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+    cache: Option<Arc<DecisionCache>>,
+    /// Whether checks may be answered from `cache`. Writes invalidate it either way.
+    use_cache: bool,
+    /// Shared by every handle, so that identical checks with the same deadline coalesce.
+    checks: Arc<CheckCoalescer>,
+    config: Arc<OpenFgaConfig>,
 }
 
-pub async fn list_stores(
-    page_size: Option<i32>,
-    continuation_token: Option<&str>,
-) -> Result<tonic::Response<ListStoresResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+impl UrkelClient {
+    /// Builds a client for the OpenFGA instance at `base_path` authenticating with a preshared
+    /// token, leaving every other option at its default.
+    pub fn new(
+        base_path: &str,
+        bearer_token: &str,
+    ) -> Result<UrkelClient, Box<dyn std::error::Error>> {
+        let config = OpenFgaConfig {
+            api_url: base_path.to_owned(),
+            credentials: CredentialsConfig {
+                method: CredentialsMethod::ApiToken,
+                api_token: Some(bearer_token.to_owned()),
+                ..CredentialsConfig::default()
+            },
+            ..OpenFgaConfig::default()
+        };
+        UrkelClient::from_config(&config)
+    }
 
-    let request = tonic::Request::new(ListStoresRequest {
-        page_size: page_size,
-        continuation_token: continuation_token.unwrap_or("").into(),
-    });
+    /// Builds a client from a validated configuration, speaking the configured transport. This
+    /// does not wait for OpenFGA to be reachable.
+    pub fn from_config(config: &OpenFgaConfig) -> Result<UrkelClient, Box<dyn std::error::Error>> {
+        config.validate()?;
 
-    let response = client.list_stores(request).await?;
-    Ok(response)
-}
+        let credentials = Credentials::from_config(&config.credentials)
+            .map_err(|error| error as Box<dyn std::error::Error>)?;
+        let backend: Arc<dyn OpenFgaBackend> = match config.transport {
+            Transport::Grpc => Arc::new(GrpcBackend::new(config, credentials.clone())?),
+            Transport::Http => Arc::new(RestBackend::new(config, credentials.clone())?),
+        };
+        UrkelClient::with_backend(backend, credentials, config)
+    }
 
-pub async fn create_store(
-    body: CreateStoreRequest,
-) -> Result<tonic::Response<CreateStoreResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+    /// Builds a client sending its calls through `backend`, which authenticates them with
+    /// `credentials`. Deadlines, retries and defaults are still taken from `config`.
+    pub fn with_backend(
+        backend: Arc<dyn OpenFgaBackend>,
+        credentials: Credentials,
+        config: &OpenFgaConfig,
+    ) -> Result<UrkelClient, Box<dyn std::error::Error>> {
+        config.validate()?;
+        Ok(UrkelClient {
+            backend,
+            credentials,
+            retry: RetryPolicy::new(&config.retry),
+            timeout: None,
+            cache: config
+                .cache
+                .enabled
+                .then(|| Arc::new(DecisionCache::new(&config.cache))),
+            use_cache: true,
+            checks: Arc::new(CheckCoalescer::default()),
+            config: Arc::new(config.clone()),
+        })
+    }
 
-    let response = client.create_store(body).await?;
-    Ok(response)
-}
+    /// A handle to the same backend whose calls use `timeout` as their deadline, and as the
+    /// budget of composite operations, instead of the configured ones.
+    pub fn with_timeout(&self, timeout: Duration) -> UrkelClient {
+        UrkelClient {
+            timeout: Some(timeout),
+            ..self.clone()
+        }
+    }
 
-pub async fn delete_store(store_id: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+    /// A handle to the same backend whose checks always reach OpenFGA, for callers which cannot
+    /// accept a cached decision.
+    pub fn without_cache(&self) -> UrkelClient {
+        UrkelClient {
+            use_cache: false,
+            ..self.clone()
+        }
+    }
 
-    let request = tonic::Request::new(DeleteStoreRequest {
-        store_id: store_id.into(),
-    });
+    /// How the checks of this client and the handles sharing its backend were answered.
+    pub fn check_metrics(&self) -> CheckMetrics {
+        self.checks.metrics()
+    }
 
-    client.delete_store(request).await?;
-    Ok(())
-}
+    /// The store configured for callers that do not track a store of their own.
+    pub fn default_store_id(&self) -> Option<&str> {
+        self.config.store_id.as_deref()
+    }
 
-pub async fn read_authorization_models(
-    store_id: &str,
-    page_size: Option<i32>,
-    continuation_token: Option<&str>,
-) -> Result<tonic::Response<ReadAuthorizationModelsResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(ReadAuthorizationModelsRequest {
-        store_id: Some(store_id.to_string()),
-        page_size: page_size,
-        continuation_token: continuation_token.unwrap_or("").into(),
-    });
-
-    let response = client.read_authorization_models(request).await?;
-    Ok(response)
-}
+    /// Falls back to the configured authorization model when a request does not name one.
+    fn authorization_model_id(&self, authorization_model_id: Option<String>) -> Option<String> {
+        authorization_model_id
+            .filter(|id| !id.is_empty())
+            .or_else(|| self.config.authorization_model_id.clone())
+    }
 
-pub async fn write_authorization_model(
-    store_id: &str,
-    body: WriteAuthorizationModelRequest,
-) -> Result<tonic::Response<WriteAuthorizationModelResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+    /// Forgets the cached decisions of a store whose tuples or models may have changed, and
+    /// keeps its later checks from joining those in flight.
+    fn invalidate_cache(&self, store_id: &str) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_store(store_id);
+        }
+        self.checks.invalidate_store(store_id);
+    }
 
-    let request = tonic::Request::new(WriteAuthorizationModelRequest {
-        store_id: Some(store_id.to_string()),
-        type_definitions: body.type_definitions,
-        schema_version: body.schema_version,
-    });
+    async fn refresh_credentials(&self) -> Result<(), UrkelError> {
+        self.credentials.refresh().await.map_err(|error| {
+            UrkelError::internal(
+                InternalErrorCode::Unavailable,
+                format!("Could not obtain an OpenFGA access token: {}", error),
+            )
+        })
+    }
 
-    let response = client.write_authorization_model(request).await?;
-    Ok(response)
-}
+    /// Sends a call to OpenFGA, repeating it as allowed by the retry policy until the deadline
+    /// of the operation. `call` is given the time left; credentials are refreshed before every
+    /// attempt.
+    async fn call<T, F, Fut>(
+        &self,
+        operation: Operation,
+        idempotency: Idempotency,
+        mut call: F,
+    ) -> Result<T, UrkelError>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, BackendError>>,
+    {
+        let timeout = self
+            .timeout
+            .unwrap_or_else(|| self.config.timeouts.operation(operation));
+        let deadline = Instant::now() + timeout;
+        let attempts = async {
+            let mut attempt = 1;
+            loop {
+                self.refresh_credentials().await?;
+                // Lets OpenFGA give up on the call as well.
+                let error = match call(deadline.saturating_duration_since(Instant::now())).await {
+                    Ok(response) => return Ok(response),
+                    Err(error) => error,
+                };
+                // tonic reports its own expired deadlines as cancellations.
+                if Instant::now() >= deadline {
+                    return Err(UrkelError::deadline_exceeded(timeout));
+                }
+                match self.retry.backoff(attempt, &error, idempotency) {
+                    Some(backoff) if Instant::now() + backoff < deadline => {
+                        rocket::tokio::time::sleep(backoff).await;
+                        attempt += 1;
+                    }
+                    _ => return Err(error.error),
+                }
+            }
+        };
+        rocket::tokio::time::timeout(timeout, attempts)
+            .await
+            .unwrap_or_else(|_| Err(UrkelError::deadline_exceeded(timeout)))
+    }
 
-pub async fn read_authorization_model(
-    store_id: &str,
-    id: &str,
-) -> Result<tonic::Response<ReadAuthorizationModelResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+    /// Runs an operation made of several calls within the composite budget.
+    async fn within_budget<T>(
+        &self,
+        operation: impl Future<Output = Result<T, UrkelError>>,
+    ) -> Result<T, UrkelError> {
+        let budget = self
+            .timeout
+            .unwrap_or_else(|| self.config.timeouts.composite());
+        rocket::tokio::time::timeout(budget, operation)
+            .await
+            .unwrap_or_else(|_| Err(UrkelError::deadline_exceeded(budget)))
+    }
 
-    let request = tonic::Request::new(ReadAuthorizationModelRequest {
-        store_id: Some(store_id.to_string()),
-        id: id.into(),
-    });
+    pub async fn get_store(
+        &self,
+        store_id: &str,
+    ) -> Result<tonic::Response<GetStoreResponse>, UrkelError> {
+        let request = GetStoreRequest {
+            store_id: store_id.into(),
+        };
 
-    let response = client.read_authorization_model(request).await?;
-    Ok(response)
-}
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend.get_store(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-pub async fn read_changes(
-    store_id: &str,
-    r#type: Option<&str>,
-    page_size: Option<i32>,
-    continuation_token: Option<&str>,
-) -> Result<tonic::Response<ReadChangesResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(ReadChangesRequest {
-        store_id: Some(store_id.to_string()),
-        r#type: r#type.unwrap_or("").into(),
-        page_size: page_size,
-        continuation_token: continuation_token.unwrap_or("").into(),
-    });
-
-    let response = client.read_changes(request).await?;
-    Ok(response)
-}
+    pub async fn list_stores(
+        &self,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> Result<tonic::Response<ListStoresResponse>, UrkelError> {
+        let request = ListStoresRequest {
+            page_size,
+            continuation_token: continuation_token.unwrap_or("").into(),
+        };
 
-pub async fn read(
-    store_id: &str,
-    body: ReadRequest,
-) -> Result<tonic::Response<ReadResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(ReadRequest {
-        store_id: Some(store_id.to_string()),
-        tuple_key: body.tuple_key,
-        page_size: body.page_size,
-        continuation_token: body.continuation_token.into(),
-    });
-
-    let response = client.read(request).await?;
-    Ok(response)
-}
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend.list_stores(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-pub async fn write(
-    store_id: &str,
-    body: WriteRequest,
-) -> Result<tonic::Response<WriteResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(WriteRequest {
-        store_id: Some(store_id.to_string()),
-        writes: body.writes,
-        deletes: body.deletes,
-        authorization_model_id: body.authorization_model_id,
-    });
-
-    let response = client.write(request).await?;
-    Ok(response)
-}
+    pub async fn create_store(
+        &self,
+        body: CreateStoreRequest,
+    ) -> Result<tonic::Response<CreateStoreResponse>, UrkelError> {
+        let response = self
+            .call(Operation::Write, Idempotency::NonIdempotent, |timeout| {
+                self.backend.create_store(body.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-pub async fn check(
-    store_id: &str,
-    body: CheckRequest,
-) -> Result<tonic::Response<CheckResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(CheckRequest {
-        store_id: Some(store_id.to_string()),
-        tuple_key: body.tuple_key,
-        contextual_tuples: body.contextual_tuples,
-        authorization_model_id: body.authorization_model_id,
-        trace: body.trace,
-    });
-
-    let response = client.check(request).await?;
-    Ok(response)
-}
+    pub async fn update_store(
+        &self,
+        store_id: &str,
+        body: UpdateStoreRequest,
+    ) -> Result<tonic::Response<UpdateStoreResponse>, UrkelError> {
+        let request = UpdateStoreRequest {
+            store_id: store_id.into(),
+            name: body.name,
+        };
 
-pub async fn expand(
-    store_id: &str,
-    body: ExpandRequest,
-) -> Result<tonic::Response<ExpandResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+        let response = self
+            .call(Operation::Write, Idempotency::Idempotent, |timeout| {
+                self.backend.update_store(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-    let request = tonic::Request::new(ExpandRequest {
-        store_id: Some(store_id.to_string()),
-        tuple_key: body.tuple_key,
-        authorization_model_id: body.authorization_model_id,
-    });
+    pub async fn delete_store(&self, store_id: &str) -> Result<(), UrkelError> {
+        let request = DeleteStoreRequest {
+            store_id: store_id.into(),
+        };
 
-    let response = client.expand(request).await?;
-    Ok(response)
-}
+        let response = self
+            .call(Operation::Write, Idempotency::NonIdempotent, |timeout| {
+                self.backend.delete_store(request.clone(), timeout)
+            })
+            .await;
+        self.invalidate_cache(store_id);
+        response.map(drop)
+    }
 
-pub async fn list_objects(
-    store_id: &str,
-    body: ListObjectsRequest,
-) -> Result<tonic::Response<ListObjectsResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(ListObjectsRequest {
-        store_id: Some(store_id.to_string()),
-        r#type: body.r#type,
-        relation: body.relation,
-        user: body.user,
-        contextual_tuples: body.contextual_tuples,
-        authorization_model_id: body.authorization_model_id,
-    });
-
-    let response = client.list_objects(request).await?;
-    Ok(response)
-}
+    pub async fn read_authorization_models(
+        &self,
+        store_id: &str,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> Result<tonic::Response<ReadAuthorizationModelsResponse>, UrkelError> {
+        let request = ReadAuthorizationModelsRequest {
+            store_id: Some(store_id.to_string()),
+            page_size,
+            continuation_token: continuation_token.unwrap_or("").into(),
+        };
 
-pub async fn read_assertions(
-    store_id: &str,
-    authorization_model_id: &str,
-) -> Result<tonic::Response<ReadAssertionsResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend
+                    .read_authorization_models(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-    let request = tonic::Request::new(ReadAssertionsRequest {
-        store_id: store_id.to_string(),
-        authorization_model_id: authorization_model_id.into(),
-    });
+    pub async fn write_authorization_model(
+        &self,
+        store_id: &str,
+        body: WriteAuthorizationModelRequest,
+    ) -> Result<tonic::Response<WriteAuthorizationModelResponse>, UrkelError> {
+        let request = WriteAuthorizationModelRequest {
+            store_id: Some(store_id.to_string()),
+            type_definitions: body.type_definitions,
+            schema_version: body.schema_version,
+        };
 
-    let response = client.read_assertions(request).await?;
-    Ok(response)
-}
+        // Checks naming no model were resolved against the previous latest one.
+        let response = self
+            .call(Operation::Write, Idempotency::NonIdempotent, |timeout| {
+                self.backend
+                    .write_authorization_model(request.clone(), timeout)
+            })
+            .await;
+        self.invalidate_cache(store_id);
+        Ok(tonic::Response::new(response?))
+    }
 
-pub async fn write_assertions(
-    store_id: &str,
-    authorization_model_id: &str,
-    body: WriteAssertionsRequest,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
-
-    let request = tonic::Request::new(WriteAssertionsRequest {
-        store_id: Some(store_id.to_string()),
-        authorization_model_id: authorization_model_id.into(),
-        assertions: body.assertions,
-    });
-
-    client.write_assertions(request).await?;
-    Ok(())
-}
+    pub async fn read_authorization_model(
+        &self,
+        store_id: &str,
+        id: &str,
+    ) -> Result<tonic::Response<ReadAuthorizationModelResponse>, UrkelError> {
+        let request = ReadAuthorizationModelRequest {
+            store_id: Some(store_id.to_string()),
+            id: id.into(),
+        };
+
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend
+                    .read_authorization_model(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-pub async fn read_until_end(
-    store_id: &str,
-    body: ReadRequest,
-) -> Result<tonic::Response<ReadResponse>, Box<dyn std::error::Error>> {
-    let mut client = get_default_client().await?;
+    pub async fn read_changes(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> Result<tonic::Response<ReadChangesResponse>, UrkelError> {
+        let request = ReadChangesRequest {
+            store_id: Some(store_id.to_string()),
+            r#type: r#type.unwrap_or("").into(),
+            page_size,
+            continuation_token: continuation_token.unwrap_or("").into(),
+        };
 
-    let mut local_var_continuation_token = Some(body.continuation_token);
-    let mut tuples = Vec::new();
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend.read_changes(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-    while let Some(ref_continuation_token) = local_var_continuation_token {
-        let loop_request = tonic::Request::new(ReadRequest {
+    pub async fn read(
+        &self,
+        store_id: &str,
+        body: ReadRequest,
+    ) -> Result<tonic::Response<ReadResponse>, UrkelError> {
+        let request = ReadRequest {
             store_id: Some(store_id.to_string()),
-            tuple_key: body.tuple_key.clone(),
-            page_size: body.page_size.clone(),
-            continuation_token: ref_continuation_token,
-        });
+            tuple_key: body.tuple_key,
+            page_size: body.page_size,
+            continuation_token: body.continuation_token,
+        };
 
-        let loop_response = client.read(loop_request).await?.into_inner();
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend.read(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-        tuples.extend(loop_response.tuples);
+    pub async fn write(
+        &self,
+        store_id: &str,
+        body: WriteRequest,
+    ) -> Result<tonic::Response<WriteResponse>, UrkelError> {
+        let request = WriteRequest {
+            store_id: Some(store_id.to_string()),
+            writes: body.writes,
+            deletes: body.deletes,
+            authorization_model_id: self.authorization_model_id(body.authorization_model_id),
+        };
 
-        if loop_response.continuation_token != "" {
-            local_var_continuation_token = Some(loop_response.continuation_token);
-        } else {
-            local_var_continuation_token = None;
-        }
+        // Failed writes may still have been applied.
+        let response = self
+            .call(Operation::Write, Idempotency::NonIdempotent, |timeout| {
+                self.backend.write(request.clone(), timeout)
+            })
+            .await;
+        self.invalidate_cache(store_id);
+        Ok(tonic::Response::new(response?))
     }
-    let new_response = tonic::Response::new(ReadResponse {
-        tuples: tuples,
-        continuation_token: "".to_owned(),
-    });
-    Ok(new_response)
-}
 
-pub async fn batch_check(
-    store_id: &str,
-    bodies: Vec<CheckRequest>,
-) -> Vec<Result<BatchCheckResponse, BatchCheckResponse>> {
-    let local_var_futures = stream::iter(bodies)
-        .map(|body| async move {
-            let mut client = get_default_client().await.expect("building tonic client");
-
-            let request = tonic::Request::new(CheckRequest {
-                store_id: Some(store_id.to_string()),
-                tuple_key: body.tuple_key.clone(),
-                contextual_tuples: body.contextual_tuples.clone(),
-                authorization_model_id: body.authorization_model_id.clone(),
-                trace: body.trace.clone(),
-            });
-
-            let response = client.check(request).await;
-            match response {
-                Ok(check) => Ok(BatchCheckResponse {
-                    allowed: Some(check.into_inner().allowed),
-                    request: Some(body),
-                    err: None,
-                }),
-                Err(error) => Ok(BatchCheckResponse {
-                    allowed: Some(false),
-                    request: Some(body),
-                    err: Some(error.to_string()),
-                }),
+    pub async fn check(
+        &self,
+        store_id: &str,
+        body: CheckRequest,
+    ) -> Result<tonic::Response<CheckResponse>, UrkelError> {
+        let request = CheckRequest {
+            store_id: Some(store_id.to_string()),
+            tuple_key: body.tuple_key,
+            contextual_tuples: body.contextual_tuples,
+            authorization_model_id: self.authorization_model_id(body.authorization_model_id),
+            trace: body.trace,
+        };
+
+        // Traces describe a resolution, which is not worth caching.
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| self.use_cache && request.trace != Some(true));
+        if let Some(response) = cache.and_then(|cache| cache.get(&request)) {
+            self.checks.cached();
+            return Ok(tonic::Response::new(response));
+        }
+        let generation = cache.map(|cache| cache.generation(store_id));
+
+        let client = self.clone();
+        let call = {
+            let request = request.clone();
+            async move {
+                client
+                    .call(Operation::Check, Idempotency::Idempotent, |timeout| {
+                        client.backend.check(request.clone(), timeout)
+                    })
+                    .await
             }
-        })
-        .buffer_unordered(CONCURRENT_REQUESTS);
+        };
+        let response = self.checks.check(&request, self.timeout, call).await?;
+        if let (Some(cache), Some(generation)) = (cache, generation) {
+            cache.insert(&request, response.clone(), generation);
+        }
+        Ok(tonic::Response::new(response))
+    }
 
-    let results = local_var_futures
-        .collect::<Vec<Result<BatchCheckResponse, BatchCheckResponse>>>()
-        .await;
-    results
-}
+    pub async fn expand(
+        &self,
+        store_id: &str,
+        body: ExpandRequest,
+    ) -> Result<tonic::Response<ExpandResponse>, UrkelError> {
+        let request = ExpandRequest {
+            store_id: Some(store_id.to_string()),
+            tuple_key: body.tuple_key,
+            authorization_model_id: self
+                .authorization_model_id(Some(body.authorization_model_id))
+                .unwrap_or_default(),
+        };
+
+        let response = self
+            .call(Operation::Expand, Idempotency::Idempotent, |timeout| {
+                self.backend.expand(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
 
-pub async fn check_n_of_m(
-    store_id: &str,
-    body: CheckNOfMRequest,
-) -> Result<tonic::Response<CheckResponse>, Box<dyn std::error::Error>> {
-    let n = body.num;
-    let checks = body.checks;
-    if checks.len() < 1 {
-        let validation_error = crate::models::ValidationErrorMessageResponse {
-            code: Some(crate::models::ErrorCode::ValidationError),
-            message: Some("Must provide at least one check.".into()),
+    pub async fn list_objects(
+        &self,
+        store_id: &str,
+        body: ListObjectsRequest,
+    ) -> Result<tonic::Response<ListObjectsResponse>, UrkelError> {
+        let request = ListObjectsRequest {
+            store_id: Some(store_id.to_string()),
+            r#type: body.r#type,
+            relation: body.relation,
+            user: body.user,
+            contextual_tuples: body.contextual_tuples,
+            authorization_model_id: self.authorization_model_id(body.authorization_model_id),
         };
-        return Err(Box::new(validation_error));
+
+        let response = self
+            .call(Operation::ListObjects, Idempotency::Idempotent, |timeout| {
+                self.backend.list_objects(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
     }
-    if n <= 0 || n > (checks.len() - 1) {
-        let validation_error = crate::models::ValidationErrorMessageResponse {
-            code: Some(crate::models::ErrorCode::ValidationError),
-            message: Some("Invalid n value provided.".into()),
+
+    /// Streams the objects found by OpenFGA as they are found, without the result limit of
+    /// `list_objects`. The deadline of `ListObjects` calls bounds the whole stream.
+    pub async fn streamed_list_objects(
+        &self,
+        store_id: &str,
+        body: StreamedListObjectsRequest,
+    ) -> Result<BoxStream<'static, Result<StreamedListObjectsResponse, UrkelError>>, UrkelError>
+    {
+        let request = StreamedListObjectsRequest {
+            store_id: Some(store_id.to_string()),
+            r#type: body.r#type,
+            relation: body.relation,
+            user: body.user,
+            contextual_tuples: body.contextual_tuples,
+            authorization_model_id: self
+                .authorization_model_id(Some(body.authorization_model_id))
+                .unwrap_or_default(),
+        };
+
+        let results = self
+            .call(Operation::ListObjects, Idempotency::Idempotent, |timeout| {
+                self.backend.streamed_list_objects(request.clone(), timeout)
+            })
+            .await?;
+        Ok(results
+            .map(|result| result.map_err(|error| error.error))
+            .boxed())
+    }
+
+    pub async fn read_assertions(
+        &self,
+        store_id: &str,
+        authorization_model_id: &str,
+    ) -> Result<tonic::Response<ReadAssertionsResponse>, UrkelError> {
+        let request = ReadAssertionsRequest {
+            store_id: store_id.to_string(),
+            authorization_model_id: authorization_model_id.into(),
+        };
+
+        let response = self
+            .call(Operation::Read, Idempotency::Idempotent, |timeout| {
+                self.backend.read_assertions(request.clone(), timeout)
+            })
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
+
+    pub async fn write_assertions(
+        &self,
+        store_id: &str,
+        authorization_model_id: &str,
+        body: WriteAssertionsRequest,
+    ) -> Result<(), UrkelError> {
+        let request = WriteAssertionsRequest {
+            store_id: Some(store_id.to_string()),
+            authorization_model_id: authorization_model_id.into(),
+            assertions: body.assertions,
         };
-        return Err(Box::new(validation_error));
-    }
-    let results: Vec<Result<BatchCheckResponse, BatchCheckResponse>> =
-        batch_check(store_id, checks).await;
-    let results = results
-        .into_iter()
-        .filter_map(|result| result.ok())
-        .filter(|result| result.allowed.unwrap_or(false))
-        .collect::<Vec<_>>();
-    if results.len() >= n {
-        Ok(tonic::Response::new(CheckResponse {
-            allowed: true,
-            resolution: None,
+
+        self.call(Operation::Write, Idempotency::Idempotent, |timeout| {
+            self.backend.write_assertions(request.clone(), timeout)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Every store from `continuation_token` on, fetching a page whenever the previous one was
+    /// consumed.
+    pub fn list_stores_stream(
+        &self,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> BoxStream<'static, Result<Store, UrkelError>> {
+        let client = self.clone();
+        pagination::items(pagination::pages(
+            continuation_token.map(str::to_owned),
+            move |continuation_token| {
+                let client = client.clone();
+                async move {
+                    let response = client
+                        .list_stores(page_size, continuation_token.as_deref())
+                        .await?;
+                    Ok(response.into_inner())
+                }
+            },
+        ))
+    }
+
+    /// Every authorization model of a store from `continuation_token` on, newest first.
+    pub fn read_authorization_models_stream(
+        &self,
+        store_id: &str,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> BoxStream<'static, Result<AuthorizationModel, UrkelError>> {
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        pagination::items(pagination::pages(
+            continuation_token.map(str::to_owned),
+            move |continuation_token| {
+                let client = client.clone();
+                let store_id = store_id.clone();
+                async move {
+                    let response = client
+                        .read_authorization_models(
+                            &store_id,
+                            page_size,
+                            continuation_token.as_deref(),
+                        )
+                        .await?;
+                    Ok(response.into_inner())
+                }
+            },
+        ))
+    }
+
+    /// The pages of changes from `continuation_token` on, up to the latest change.
+    fn read_changes_pages(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> BoxStream<'static, Result<ReadChangesResponse, UrkelError>> {
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        let r#type = r#type.map(str::to_owned);
+        pagination::pages(
+            continuation_token.map(str::to_owned),
+            move |continuation_token| {
+                let client = client.clone();
+                let store_id = store_id.clone();
+                let r#type = r#type.clone();
+                async move {
+                    let response = client
+                        .read_changes(
+                            &store_id,
+                            r#type.as_deref(),
+                            page_size,
+                            continuation_token.as_deref(),
+                        )
+                        .await?;
+                    Ok(response.into_inner())
+                }
+            },
+        )
+    }
+
+    /// Every change of a store from `continuation_token` on, up to the latest change.
+    pub fn read_changes_stream(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> BoxStream<'static, Result<TupleChange, UrkelError>> {
+        pagination::items(self.read_changes_pages(store_id, r#type, page_size, continuation_token))
+    }
+
+    /// The token following the latest change of a store, which is empty when the store has no
+    /// changes yet.
+    async fn latest_changes_token(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+    ) -> Result<String, UrkelError> {
+        let mut pages = self.read_changes_pages(store_id, r#type, page_size, None);
+        let mut continuation_token = String::new();
+        while let Some(page) = pages.try_next().await? {
+            if !page.continuation_token.is_empty() {
+                continuation_token = page.continuation_token;
+            }
+        }
+        Ok(continuation_token)
+    }
+
+    /// The pages of changes of a store from `continuation_token` on, or from its latest change
+    /// when there is none. Once the latest change was read, OpenFGA is polled for new ones every
+    /// `watcher.poll_interval_ms`. The stream ends after the first error. The changes of a store
+    /// watched by a `ChangeWatcher` are better followed with `ChangeWatcher::changes`.
+    pub fn watch_changes(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> BoxStream<'static, Result<ReadChangesResponse, UrkelError>> {
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        let r#type = r#type.map(str::to_owned);
+        let poll_interval = self.config.watcher.poll_interval();
+        let start = continuation_token.map(str::to_owned);
+        stream::try_unfold(start, move |continuation_token| {
+            let client = client.clone();
+            let store_id = store_id.clone();
+            let r#type = r#type.clone();
+            async move {
+                let mut continuation_token = match continuation_token {
+                    Some(continuation_token) => continuation_token,
+                    None => {
+                        client
+                            .latest_changes_token(&store_id, r#type.as_deref(), page_size)
+                            .await?
+                    }
+                };
+                loop {
+                    let page = client
+                        .read_changes(
+                            &store_id,
+                            r#type.as_deref(),
+                            page_size,
+                            Some(&continuation_token),
+                        )
+                        .await?
+                        .into_inner();
+                    if !page.changes.is_empty() {
+                        let next = Some(page.continuation_token.clone());
+                        return Ok(Some((page, next)));
+                    }
+                    if !page.continuation_token.is_empty() {
+                        continuation_token = page.continuation_token;
+                    }
+                    rocket::tokio::time::sleep(poll_interval).await;
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Every tuple matching `body.tuple_key`, from `body.continuation_token` on.
+    pub fn read_stream(
+        &self,
+        store_id: &str,
+        body: ReadRequest,
+    ) -> BoxStream<'static, Result<Tuple, UrkelError>> {
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        let continuation_token = Some(body.continuation_token.clone());
+        pagination::items(pagination::pages(
+            continuation_token,
+            move |continuation_token| {
+                let client = client.clone();
+                let store_id = store_id.clone();
+                let request = ReadRequest {
+                    continuation_token: continuation_token.unwrap_or_default(),
+                    ..body.clone()
+                };
+                async move { Ok(client.read(&store_id, request).await?.into_inner()) }
+            },
+        ))
+    }
+
+    pub async fn list_stores_until_end(
+        &self,
+        page_size: Option<i32>,
+    ) -> Result<tonic::Response<ListStoresResponse>, UrkelError> {
+        let stores = self
+            .list_stores_stream(page_size, None)
+            .try_collect()
+            .await?;
+        Ok(tonic::Response::new(ListStoresResponse {
+            stores,
+            continuation_token: "".to_owned(),
         }))
-    } else {
-        Ok(tonic::Response::new(CheckResponse {
-            allowed: false,
-            resolution: None,
+    }
+
+    pub async fn read_authorization_models_until_end(
+        &self,
+        store_id: &str,
+        page_size: Option<i32>,
+    ) -> Result<tonic::Response<ReadAuthorizationModelsResponse>, UrkelError> {
+        let authorization_models = self
+            .read_authorization_models_stream(store_id, page_size, None)
+            .try_collect()
+            .await?;
+        Ok(tonic::Response::new(ReadAuthorizationModelsResponse {
+            authorization_models,
+            continuation_token: "".to_owned(),
         }))
     }
-}
 
-pub async fn check_horizontal(
-    store_id: &str,
-    body: CheckHorizontalRequest,
-) -> Result<tonic::Response<CheckResponse>, Box<dyn std::error::Error>> {
-    let read_from = ReadRequest {
-        store_id: Some(store_id.to_string()),
-        tuple_key: Some(TupleKey {
-            object: Some(body.read_from.object.clone()),
-            relation: Some(body.read_from.relation.clone()),
-            user: None,
-        }),
-        page_size: Some(100),
-        continuation_token: "".to_owned(),
-    };
+    /// Every change from `continuation_token` on. The returned token is the one to poll for
+    /// later changes.
+    pub async fn read_changes_until_end(
+        &self,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> Result<tonic::Response<ReadChangesResponse>, UrkelError> {
+        let mut pages = self.read_changes_pages(store_id, r#type, page_size, continuation_token);
+        let mut response = ReadChangesResponse {
+            changes: Vec::new(),
+            continuation_token: continuation_token.unwrap_or("").to_owned(),
+        };
+        while let Some(page) = pages.try_next().await? {
+            response.changes.extend(page.changes);
+            if !page.continuation_token.is_empty() {
+                response.continuation_token = page.continuation_token;
+            }
+        }
+        Ok(tonic::Response::new(response))
+    }
+
+    /// The pages of tuples from `body.continuation_token` on, until the end or a limit, then
+    /// where the read stopped. Pages are shrunk so that the tuple limit falls on a page boundary,
+    /// which keeps the continuation token of the last page exact. OpenFGA answers full pages
+    /// with a continuation token even when no tuple is left, so a read stopped by a limit looks
+    /// for one more tuple before calling itself truncated.
+    fn read_pages(
+        &self,
+        store_id: &str,
+        body: ReadRequest,
+        limits: ReadLimits,
+    ) -> BoxStream<'static, Result<ReadPage, UrkelError>> {
+        let configured = &self.config.read_limits;
+        let max_tuples = limits
+            .max_tuples
+            .map_or(configured.max_tuples, |max| max.min(configured.max_tuples));
+        let max_pages = match (limits.max_pages, configured.max_pages) {
+            (Some(max), Some(configured)) => Some(max.min(configured)),
+            (max, configured) => max.or(configured),
+        };
+        let page_size = body.page_size.unwrap_or(DEFAULT_READ_PAGE_SIZE);
+
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        let start = ReadState::Reading {
+            continuation_token: body.continuation_token.clone(),
+            tuples: 0,
+            pages: 0,
+        };
+        stream::try_unfold(start, move |state| {
+            let client = client.clone();
+            let store_id = store_id.clone();
+            let body = body.clone();
+            async move {
+                let (continuation_token, tuples, pages) = match state {
+                    ReadState::Reading {
+                        continuation_token,
+                        tuples,
+                        pages,
+                    } => (continuation_token, tuples, pages),
+                    ReadState::Ended(end) => {
+                        return Ok(Some((ReadPage::End(end), ReadState::Done)))
+                    }
+                    ReadState::Done => return Ok(None),
+                };
+
+                if tuples >= max_tuples || max_pages.is_some_and(|max| pages >= max) {
+                    let request = ReadRequest {
+                        page_size: Some(1),
+                        continuation_token: continuation_token.clone(),
+                        ..body
+                    };
+                    let left = client.read(&store_id, request).await?.into_inner();
+                    let end = if left.tuples.is_empty() {
+                        ReadEnd::default()
+                    } else {
+                        ReadEnd {
+                            continuation_token,
+                            truncated: true,
+                        }
+                    };
+                    return Ok(Some((ReadPage::End(end), ReadState::Done)));
+                }
+
+                let remaining = i32::try_from(max_tuples - tuples).unwrap_or(i32::MAX);
+                let request = ReadRequest {
+                    page_size: Some(page_size.min(remaining)),
+                    continuation_token,
+                    ..body
+                };
+                let page = client.read(&store_id, request).await?.into_inner();
+                let state = if page.continuation_token.is_empty() {
+                    ReadState::Ended(ReadEnd::default())
+                } else {
+                    ReadState::Reading {
+                        continuation_token: page.continuation_token.clone(),
+                        tuples: tuples + page.tuples.len(),
+                        pages: pages + 1,
+                    }
+                };
+                Ok(Some((ReadPage::Tuples(page), state)))
+            }
+        })
+        .boxed()
+    }
+
+    /// Reads every tuple matching `body.tuple_key`, or as many as the limits allow. When a limit
+    /// was hit, the response is marked as truncated and its continuation token resumes the read.
+    pub async fn read_until_end(
+        &self,
+        store_id: &str,
+        body: ReadRequest,
+        limits: ReadLimits,
+    ) -> Result<tonic::Response<ReadUntilEndResponse>, UrkelError> {
+        let mut pages = self.read_pages(store_id, body, limits);
+        let mut response = ReadUntilEndResponse::default();
+        while let Some(page) = pages.try_next().await? {
+            match page {
+                ReadPage::Tuples(page) => response.tuples.extend(page.tuples),
+                ReadPage::End(end) => response.end = end,
+            }
+        }
+        Ok(tonic::Response::new(response))
+    }
+
+    /// Like `read_until_end`, holding a single page in memory at a time.
+    pub fn read_until_end_stream(
+        &self,
+        store_id: &str,
+        body: ReadRequest,
+        limits: ReadLimits,
+    ) -> BoxStream<'static, Result<ReadUntilEndItem, UrkelError>> {
+        self.read_pages(store_id, body, limits)
+            .map_ok(|page| {
+                let items: Vec<_> = match page {
+                    ReadPage::Tuples(page) => page
+                        .tuples
+                        .into_iter()
+                        .map(ReadUntilEndItem::Tuple)
+                        .collect(),
+                    ReadPage::End(end) => vec![ReadUntilEndItem::End(end)],
+                };
+                stream::iter(items.into_iter().map(Ok))
+            })
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Runs the checks of a batch concurrently and answers in their order. Identical checks are
+    /// only sent once. A check which failed is answered as not allowed, with its error in `err`.
+    pub async fn batch_check(
+        &self,
+        store_id: &str,
+        body: BatchCheckRequest,
+    ) -> Vec<BatchCheckResponse> {
+        let max_concurrent_checks = body
+            .max_concurrent_checks
+            .unwrap_or(self.config.max_concurrent_checks)
+            .clamp(1, self.config.max_concurrent_checks);
+
+        let mut unique_checks = Vec::new();
+        let mut positions = HashMap::new();
+        let indices = body
+            .checks
+            .iter()
+            .map(|item| {
+                *positions
+                    .entry(item.request.encode_to_vec())
+                    .or_insert_with(|| {
+                        unique_checks.push(item.request.clone());
+                        unique_checks.len() - 1
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let mut outcomes = stream::iter(unique_checks.into_iter().enumerate())
+            .map(|(index, request)| async move {
+                let outcome = self.check(store_id, request).await;
+                (index, outcome.map(|check| check.into_inner().allowed))
+            })
+            .buffer_unordered(max_concurrent_checks)
+            .collect::<Vec<_>>()
+            .await;
+        outcomes.sort_by_key(|(index, _)| *index);
+
+        body.checks
+            .into_iter()
+            .zip(indices)
+            .map(|(item, index)| {
+                let (allowed, err) = match &outcomes[index].1 {
+                    Ok(allowed) => (Some(*allowed), None),
+                    Err(error) => (Some(false), Some(error.to_string())),
+                };
+                BatchCheckResponse {
+                    correlation_id: item.correlation_id,
+                    allowed,
+                    request: Some(item.request),
+                    err,
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluates `checks` until the weights of the allowed ones reach `threshold`, or cannot
+    /// anymore.
+    async fn check_threshold(
+        &self,
+        store_id: &str,
+        checks: &[CheckRequest],
+        weights: &[u64],
+        threshold: u64,
+    ) -> Result<ThresholdCheckResponse, UrkelError> {
+        threshold::evaluate(
+            weights,
+            threshold,
+            self.config.max_concurrent_checks,
+            |index| async move {
+                let check = self.check(store_id, checks[index].clone()).await?;
+                Ok(check.into_inner().allowed)
+            },
+        )
+        .await
+    }
+
+    /// Allows when at least `n` of the checks are allowed. Checks stop as soon as the outcome is
+    /// known, and the response tells which ones passed, failed or were skipped.
+    pub async fn check_n_of_m(
+        &self,
+        store_id: &str,
+        body: CheckNOfMRequest,
+    ) -> Result<tonic::Response<ThresholdCheckResponse>, UrkelError> {
+        self.within_budget(async {
+            let n = body.num;
+            let checks = body.checks;
+            if checks.is_empty() {
+                return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Must provide at least one check.",
+                ));
+            }
+            if n == 0 || n > checks.len() {
+                return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Invalid n value provided.",
+                ));
+            }
+            let weights = vec![1; checks.len()];
+            let response = self
+                .check_threshold(store_id, &checks, &weights, n as u64)
+                .await?;
+            Ok(tonic::Response::new(response))
+        })
+        .await
+    }
 
-    let local_var_response: ReadResponse = read_until_end(store_id, read_from).await?.into_inner();
-    let mut check_requests = Vec::new();
-    let tuples = local_var_response.tuples;
+    /// Allows when the weights of the allowed checks reach the threshold, with the same early
+    /// exit and report as `check_n_of_m`.
+    pub async fn check_weighted(
+        &self,
+        store_id: &str,
+        body: CheckWeightedRequest,
+    ) -> Result<tonic::Response<ThresholdCheckResponse>, UrkelError> {
+        self.within_budget(async {
+            let weights = body
+                .checks
+                .iter()
+                .map(|check| check.weight)
+                .collect::<Vec<_>>();
+            let total_weight = threshold::total_weight(&weights)?;
+            if total_weight == 0 {
+                return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Must provide at least one check with a weight.",
+                ));
+            }
+            let threshold = match body.threshold {
+                WeightThreshold::Weight(weight) => weight,
+                WeightThreshold::Percentage(percentage)
+                    if percentage > 0.0 && percentage <= 100.0 =>
+                {
+                    // Rounding may overshoot the largest totals.
+                    (((total_weight as f64) * percentage / 100.0).ceil() as u64).min(total_weight)
+                }
+                WeightThreshold::Percentage(_) => 0,
+            };
+            if threshold == 0 || threshold > total_weight {
+                return Err(UrkelError::validation(
+                    ErrorCode::ValidationError,
+                    "Invalid threshold provided.",
+                ));
+            }
+
+            let checks = body
+                .checks
+                .into_iter()
+                .map(|check| check.request)
+                .collect::<Vec<_>>();
+            let response = self
+                .check_threshold(store_id, &checks, &weights, threshold)
+                .await?;
+            Ok(tonic::Response::new(response))
+        })
+        .await
+    }
+
+    /// Evaluates a policy expression, skipping the checks which cannot change its outcome. The
+    /// checks of the whole expression share the `max_concurrent_checks` limit.
+    pub async fn check_expression(
+        &self,
+        store_id: &str,
+        expression: PolicyExpression,
+    ) -> Result<tonic::Response<CheckResponse>, UrkelError> {
+        self.within_budget(async {
+            expression.validate()?;
+            let permits = Semaphore::new(self.config.max_concurrent_checks);
+            let allowed = self
+                .evaluate_expression(store_id, &expression, &permits)
+                .await?;
+            Ok(tonic::Response::new(CheckResponse {
+                allowed,
+                resolution: None,
+            }))
+        })
+        .await
+    }
+
+    fn evaluate_expression<'a>(
+        &'a self,
+        store_id: &'a str,
+        expression: &'a PolicyExpression,
+        permits: &'a Semaphore,
+    ) -> BoxFuture<'a, Result<bool, UrkelError>> {
+        async move {
+            match expression {
+                PolicyExpression::Check(request) => {
+                    let _permit = permits.acquire().await;
+                    let check = self.check(store_id, request.clone()).await?;
+                    Ok(check.into_inner().allowed)
+                }
+                PolicyExpression::Not(expression) => Ok(!self
+                    .evaluate_expression(store_id, expression, permits)
+                    .await?),
+                PolicyExpression::All(expressions) => {
+                    self.evaluate_at_least(store_id, expressions, expressions.len(), permits)
+                        .await
+                }
+                PolicyExpression::Any(expressions) => {
+                    self.evaluate_at_least(store_id, expressions, 1, permits)
+                        .await
+                }
+                PolicyExpression::AtLeast { num, of } => {
+                    self.evaluate_at_least(store_id, of, *num, permits).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Whether at least `num` of the expressions hold. Errors are only returned when they leave
+    /// the outcome undecided, since a negation must not turn them into an allow.
+    async fn evaluate_at_least(
+        &self,
+        store_id: &str,
+        expressions: &[PolicyExpression],
+        num: usize,
+        permits: &Semaphore,
+    ) -> Result<bool, UrkelError> {
+        threshold::at_least(expressions.len(), num, |index| {
+            self.evaluate_expression(store_id, &expressions[index], permits)
+        })
+        .await
+    }
 
-    if tuples.len() == 0 {
-        let err_message =
-            "Object relation from 'read_from' returned no results to compare to.".to_string();
-        let validation_error = crate::models::ValidationErrorMessageResponse {
-            code: Some(crate::models::ErrorCode::ValidationError),
-            message: Some(err_message.clone()),
+    fn read_userset(
+        &self,
+        store_id: &str,
+        object: String,
+        relation: String,
+        page_size: i32,
+    ) -> BoxStream<'static, Result<ReadUntilEndItem, UrkelError>> {
+        let read = ReadRequest {
+            store_id: Some(store_id.to_string()),
+            tuple_key: Some(TupleKey {
+                object: Some(object),
+                relation: Some(relation),
+                user: None,
+            }),
+            page_size: Some(page_size),
+            continuation_token: "".to_owned(),
         };
-        return Err(Box::new(validation_error));
+        self.read_until_end_stream(store_id, read, ReadLimits::default())
+    }
+
+    /// The users related to `object` by `relation`, read a page at a time as the stream is
+    /// consumed. Usersets among them, like `group:eng#member`, are expanded to their users as they
+    /// are met, so that members granted through computed relations are found too. The usersets
+    /// share one expansion, so a userset nested in several of them is only expanded once.
+    /// Wildcards like `user:*` are kept as they are, and their check tells whether every user of
+    /// the type is allowed.
+    fn resolve_users(
+        &self,
+        store_id: &str,
+        object: &str,
+        relation: &str,
+        authorization_model_id: Option<String>,
+        page_size: i32,
+    ) -> BoxStream<'static, Result<String, UrkelError>> {
+        let tuples = self.read_userset(store_id, object.to_owned(), relation.to_owned(), page_size);
+        let expansion =
+            list_users::Expansion::new(self.userset_trees(store_id, authorization_model_id));
+        let start = (
+            horizontal::Usersets::default(),
+            Some(tuples),
+            expansion,
+            VecDeque::new(),
+        );
+        stream::try_unfold(
+            start,
+            |(mut usersets, mut tuples, mut expansion, mut expanded)| async move {
+                loop {
+                    if let Some(user) = expanded.pop_front() {
+                        if let Some(user) = usersets.add_user(user) {
+                            return Ok(Some((user, (usersets, tuples, expansion, expanded))));
+                        }
+                    } else if let Some((object, relation)) = usersets.next_userset() {
+                        expanded.extend(expansion.users(&object, &relation).await?.users);
+                    } else if let Some(current) = tuples.as_mut() {
+                        match current.try_next().await? {
+                            Some(item) => {
+                                if let Some(user) = usersets.add(item)? {
+                                    let state = (usersets, tuples, expansion, expanded);
+                                    return Ok(Some((user, state)));
+                                }
+                            }
+                            None => tuples = None,
+                        }
+                    } else {
+                        return Ok(None);
+                    }
+                }
+            },
+        )
+        .boxed()
     }
 
-    tuples
-        .into_iter()
-        .map(|tuple| {
-            let mut check_request = CheckRequest {
-                store_id: Some(store_id.to_string()),
+    /// Checks `check_for` for the users found via `read_from`, allowing when as many of them as
+    /// the mode asks for are allowed. Users are checked as their pages are read, and reading stops
+    /// as soon as the outcome is known.
+    pub async fn check_horizontal(
+        &self,
+        store_id: &str,
+        body: CheckHorizontalRequest,
+    ) -> Result<tonic::Response<CheckHorizontalResponse>, UrkelError> {
+        self.within_budget(async {
+            let users = self.resolve_users(
+                store_id,
+                &body.read_from.object,
+                &body.read_from.relation,
+                body.authorization_model_id.clone(),
+                body.page_size.unwrap_or(DEFAULT_HORIZONTAL_PAGE_SIZE),
+            );
+            let response = horizontal::evaluate(
+                users,
+                body.mode,
+                self.config.max_concurrent_checks,
+                |user| {
+                    let check_request = CheckRequest {
+                        store_id: Some(store_id.to_string()),
+                        tuple_key: Some(TupleKey {
+                            object: Some(body.check_for.object.clone()),
+                            relation: Some(body.check_for.relation.clone()),
+                            user: Some(user),
+                        }),
+                        authorization_model_id: body.authorization_model_id.clone(),
+                        contextual_tuples: None,
+                        trace: None,
+                    };
+                    async move {
+                        let check = self.check(store_id, check_request).await?;
+                        Ok(check.into_inner().allowed)
+                    }
+                },
+            )
+            .await?;
+            Ok(tonic::Response::new(response))
+        })
+        .await
+    }
+
+    /// Expands a userset of a store into its tree, for a `list_users::Expansion`.
+    fn userset_trees(
+        &self,
+        store_id: &str,
+        authorization_model_id: Option<String>,
+    ) -> impl Fn(String, String) -> BoxFuture<'static, Result<UsersetTree, UrkelError>> + Send {
+        let client = self.clone();
+        let store_id = store_id.to_owned();
+        move |object, relation| {
+            let client = client.clone();
+            let store_id = store_id.clone();
+            let request = ExpandRequest {
+                store_id: None,
                 tuple_key: Some(TupleKey {
-                    object: Some(body.check_for.object.clone()),
-                    relation: Some(body.check_for.relation.clone()),
+                    object: Some(object),
+                    relation: Some(relation),
                     user: None,
                 }),
-                authorization_model_id: body.authorization_model_id.clone(),
-                contextual_tuples: None,
-                trace: None,
+                authorization_model_id: authorization_model_id.clone().unwrap_or_default(),
             };
-            if let Some(tuple_key) = tuple.key {
-                if let Some(ref mut request_tuple) = &mut check_request.tuple_key {
-                    request_tuple.user = tuple_key.user.clone();
+            async move {
+                let expansion = client.expand(&store_id, request).await?;
+                Ok(expansion.into_inner().tree.unwrap_or_default())
+            }
+            .boxed()
+        }
+    }
+
+    /// Lists the users having a relation with an object, expanding it recursively within the
+    /// composite budget. Each userset met costs an expand call.
+    pub async fn list_users(
+        &self,
+        store_id: &str,
+        body: ListUsersRequest,
+    ) -> Result<tonic::Response<ListUsersResponse>, UrkelError> {
+        self.within_budget(async {
+            let response = list_users::resolve(
+                &body.object,
+                &body.relation,
+                body.user_type.as_deref(),
+                self.userset_trees(store_id, body.authorization_model_id.clone()),
+            )
+            .await?;
+            Ok(tonic::Response::new(response))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::futures::future::join;
+    use rocket::tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    pub(crate) const STORE_ID: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+
+    /// The calls received by [`openfga`]: the last segment of their path and their body.
+    pub(crate) type Calls = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// An OpenFGA stand-in reached over HTTP, answering each call with `answer` given the last
+    /// segment of its path and its body, or its query parameters for calls without one. Answers
+    /// with a `code` are sent as errors. Returns a client for it and the calls it received.
+    pub(crate) async fn openfga<A>(answer: A) -> (UrkelClient, Calls)
+    where
+        A: Fn(&str, &Value) -> Value + Send + Sync + 'static,
+    {
+        openfga_with(OpenFgaConfig::default(), answer).await
+    }
+
+    /// Like [`openfga`], with the options of `config` other than how OpenFGA is reached.
+    pub(crate) async fn openfga_with<A>(config: OpenFgaConfig, answer: A) -> (UrkelClient, Calls)
+    where
+        A: Fn(&str, &Value) -> Value + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = OpenFgaConfig {
+            api_url: format!("http://{}", listener.local_addr().unwrap()),
+            transport: Transport::Http,
+            credentials: CredentialsConfig {
+                method: CredentialsMethod::None,
+                ..CredentialsConfig::default()
+            },
+            ..config
+        };
+        let calls = Calls::default();
+        let received = calls.clone();
+        let answer = Arc::new(answer);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let received = received.clone();
+                let answer = answer.clone();
+                tokio::spawn(async move {
+                    let (path, body) = read_call(&mut stream).await;
+                    let response = answer(&path, &body);
+                    let status = if response.get("code").is_some() {
+                        "400 Bad Request"
+                    } else {
+                        "200 OK"
+                    };
+                    let response = response.to_string();
+                    received.lock().unwrap().push((path, body));
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        response.len(),
+                        response
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        (UrkelClient::from_config(&config).unwrap(), calls)
+    }
+
+    async fn read_call(stream: &mut TcpStream) -> (String, Value) {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length || read == 0 {
+                    let target = head.split(' ').nth(1).unwrap_or_default();
+                    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                    let segment = path.rsplit('/').next().unwrap_or_default().to_owned();
+                    let body = serde_json::from_str(body).unwrap_or_else(|_| {
+                        url::form_urlencoded::parse(query.as_bytes())
+                            .map(|(name, value)| (name.into_owned(), json!(value)))
+                            .collect()
+                    });
+                    return (segment, body);
                 }
             }
-            check_requests.push(check_request);
+        }
+    }
+
+    /// Answers reads from `count` tuples, with a continuation token after every full page as
+    /// OpenFGA does.
+    fn reads(count: usize) -> impl Fn(&str, &Value) -> Value + Send + Sync + 'static {
+        move |_, body| {
+            let offset = body["continuation_token"]
+                .as_str()
+                .and_then(|token| token.parse().ok())
+                .unwrap_or(0);
+            let page_size = body["page_size"].as_u64().unwrap_or(50) as usize;
+            let end = count.min(offset + page_size);
+            let tuples: Vec<_> = (offset..end)
+                .map(|index| {
+                    json!({"key": {
+                        "object": format!("doc:{}", index),
+                        "relation": "viewer",
+                        "user": "user:a",
+                    }})
+                })
+                .collect();
+            let continuation_token = if end - offset == page_size {
+                end.to_string()
+            } else {
+                String::new()
+            };
+            json!({"tuples": tuples, "continuation_token": continuation_token})
+        }
+    }
+
+    /// Answers reads of the changes in `log`, whose continuation tokens are the number of
+    /// changes read so far.
+    pub(crate) fn changes_log(
+        log: Arc<Mutex<Vec<TupleChange>>>,
+    ) -> impl Fn(&str, &Value) -> Value + Send + Sync + 'static {
+        move |_, query| {
+            let log = log.lock().unwrap();
+            let offset = query["continuation_token"]
+                .as_str()
+                .and_then(|token| token.parse().ok())
+                .unwrap_or(0)
+                .min(log.len());
+            let page_size = query["page_size"]
+                .as_str()
+                .and_then(|page_size| page_size.parse().ok())
+                .unwrap_or(50);
+            let end = log.len().min(offset + page_size);
+            let changes: Vec<_> = log[offset..end]
+                .iter()
+                .map(|change| {
+                    let mut change = serde_json::to_value(change).unwrap();
+                    change["operation"] = json!("TUPLE_OPERATION_WRITE");
+                    change
+                })
+                .collect();
+            json!({"changes": changes, "continuation_token": end.to_string()})
+        }
+    }
+
+    fn read_request(page_size: i32) -> ReadRequest {
+        ReadRequest {
+            page_size: Some(page_size),
+            ..ReadRequest::default()
+        }
+    }
+
+    fn objects(tuples: &[Tuple]) -> Vec<String> {
+        tuples
+            .iter()
+            .map(|tuple| {
+                tuple
+                    .key
+                    .clone()
+                    .unwrap_or_default()
+                    .object
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn continuation_tokens(calls: &Calls) -> Vec<String> {
+        calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| body["continuation_token"].as_str().unwrap_or("").to_owned())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn reads_until_the_end() {
+        let (client, calls) = openfga(reads(5)).await;
+
+        let response = client
+            .read_until_end(STORE_ID, read_request(2), ReadLimits::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            objects(&response.tuples),
+            vec!["doc:0", "doc:1", "doc:2", "doc:3", "doc:4"]
+        );
+        assert_eq!(response.end, ReadEnd::default());
+        assert_eq!(continuation_tokens(&calls), vec!["", "2", "4"]);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_mark_reads_ending_on_a_limit_as_truncated() {
+        let (client, calls) = openfga(reads(4)).await;
+        let limits = ReadLimits {
+            max_tuples: Some(4),
+            max_pages: None,
+        };
+
+        let response = client
+            .read_until_end(STORE_ID, read_request(2), limits)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.tuples.len(), 4);
+        assert_eq!(response.end, ReadEnd::default());
+        assert_eq!(continuation_tokens(&calls), vec!["", "2", "4"]);
+    }
+
+    #[rocket::async_test]
+    async fn marks_reads_cut_short_by_a_limit_as_truncated() {
+        let (client, calls) = openfga(reads(5)).await;
+        let limits = ReadLimits {
+            max_tuples: Some(3),
+            max_pages: None,
+        };
+
+        let response = client
+            .read_until_end(STORE_ID, read_request(2), limits)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(objects(&response.tuples), vec!["doc:0", "doc:1", "doc:2"]);
+        assert_eq!(
+            response.end,
+            ReadEnd {
+                continuation_token: "3".to_owned(),
+                truncated: true,
+            }
+        );
+        let page_sizes: Vec<_> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| body["page_size"].as_u64())
+            .collect();
+        assert_eq!(page_sizes, vec![Some(2), Some(1), Some(1)]);
+    }
+
+    #[rocket::async_test]
+    async fn streams_tuples_then_where_the_read_stopped() {
+        let (client, _) = openfga(reads(5)).await;
+        let limits = ReadLimits {
+            max_tuples: None,
+            max_pages: Some(1),
+        };
+
+        let items: Vec<_> = client
+            .read_until_end_stream(STORE_ID, read_request(2), limits)
+            .try_collect()
+            .await
+            .unwrap();
+        let tuples: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                ReadUntilEndItem::Tuple(tuple) => Some(tuple.clone()),
+                ReadUntilEndItem::End(_) => None,
+            })
+            .collect();
+        assert_eq!(objects(&tuples), vec!["doc:0", "doc:1"]);
+        assert_eq!(
+            items.last(),
+            Some(&ReadUntilEndItem::End(ReadEnd {
+                continuation_token: "2".to_owned(),
+                truncated: true,
+            }))
+        );
+
+        let items: Vec<_> = client
+            .read_until_end_stream(STORE_ID, read_request(2), ReadLimits::default())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 6);
+        assert_eq!(
+            items.last(),
+            Some(&ReadUntilEndItem::End(ReadEnd::default()))
+        );
+    }
+
+    fn check_request(user: &str) -> CheckRequest {
+        CheckRequest {
+            tuple_key: Some(TupleKey {
+                object: Some("doc:a".to_owned()),
+                relation: Some("viewer".to_owned()),
+                user: Some(user.to_owned()),
+            }),
+            ..CheckRequest::default()
+        }
+    }
+
+    /// Allows `user:a`, denies `user:b` and rejects every other user.
+    fn checks(_: &str, body: &Value) -> Value {
+        match body["tuple_key"]["user"].as_str() {
+            Some("user:a") => json!({"allowed": true}),
+            Some("user:b") => json!({"allowed": false}),
+            _ => json!({"code": "invalid_user", "message": "unknown user"}),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn answers_failed_checks_of_a_batch_as_not_allowed() {
+        let (client, calls) = openfga(checks).await;
+        let body = BatchCheckRequest {
+            checks: ["user:a", "user:b", "bob", "user:a"]
+                .into_iter()
+                .enumerate()
+                .map(|(index, user)| BatchCheckItem {
+                    correlation_id: Some(index.to_string()),
+                    request: check_request(user),
+                })
+                .collect(),
+            max_concurrent_checks: None,
+        };
+
+        let responses = client.batch_check(STORE_ID, body).await;
+        let answers: Vec<_> = responses
+            .iter()
+            .map(|response| {
+                (
+                    response.correlation_id.as_deref().unwrap(),
+                    response.allowed,
+                    response.err.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                ("0", Some(true), false),
+                ("1", Some(false), false),
+                ("2", Some(false), true),
+                ("3", Some(true), false),
+            ]
+        );
+        assert_eq!(
+            responses[2].err.as_deref(),
+            Some("invalid_user: unknown user")
+        );
+        assert_eq!(calls.lock().unwrap().len(), 3);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_empty_combinations_of_checks() {
+        let (client, calls) = openfga(checks).await;
+        let check = || PolicyExpression::Check(check_request("user:a"));
+        let empty_expressions = [
+            json!({"all": []}),
+            json!({"any": []}),
+            json!({"not": {"all": []}}),
+            json!({"all": [{"check": {}}, {"any": []}]}),
+        ];
+
+        for expression in empty_expressions {
+            let expression: PolicyExpression = serde_json::from_value(expression).unwrap();
+            let error = client
+                .check_expression(STORE_ID, expression)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(&error, UrkelError::Validation(response)
+                    if response.code == Some(ErrorCode::ValidationError)),
+                "{:?}",
+                error
+            );
+        }
+        assert!(calls.lock().unwrap().is_empty());
+
+        let expression = PolicyExpression::All(vec![check(), PolicyExpression::Any(vec![check()])]);
+        let response = client.check_expression(STORE_ID, expression).await.unwrap();
+        assert!(response.into_inner().allowed);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_join_checks_made_before_a_write_without_a_cache() {
+        let config = OpenFgaConfig {
+            retry: crate::config::RetryConfig {
+                initial_backoff_ms: 200,
+                jitter: 0.0,
+                ..crate::config::RetryConfig::default()
+            },
+            ..OpenFgaConfig::default()
+        };
+        let unavailable = Mutex::new(true);
+        // The first check is kept in flight by retrying it after the write.
+        let (client, _) = openfga_with(config, move |path, body| match path {
+            "check" if std::mem::take(&mut *unavailable.lock().unwrap()) => {
+                json!({"code": "unavailable", "message": "try again"})
+            }
+            "check" => checks(path, body),
+            _ => json!({}),
         })
-        .for_each(drop);
-
-    // Check one to fail eagerly since checks are homogenous
-    check(store_id, check_requests[0].clone()).await?;
-
-    let local_var_response: Vec<Result<BatchCheckResponse, BatchCheckResponse>> =
-        batch_check(store_id, check_requests).await;
-    let batch_result_len: usize = local_var_response.len();
-    let local_var_allows = local_var_response
-        .into_iter()
-        .filter_map(|result| result.ok())
-        .filter(|result| result.allowed.unwrap_or(false))
-        .collect::<Vec<_>>();
-
-    if local_var_allows.len() == batch_result_len {
-        Ok(tonic::Response::new(CheckResponse {
-            allowed: true,
-            resolution: None,
-        }))
-    } else {
-        Ok(tonic::Response::new(CheckResponse {
-            allowed: false,
-            resolution: None,
-        }))
+        .await;
+
+        let before = client.check(STORE_ID, check_request("user:a"));
+        let after = async {
+            client
+                .write(STORE_ID, WriteRequest::default())
+                .await
+                .unwrap();
+            client.check(STORE_ID, check_request("user:a")).await
+        };
+        let (before, after) = join(before, after).await;
+
+        assert!(before.unwrap().into_inner().allowed);
+        assert!(after.unwrap().into_inner().allowed);
+        assert_eq!(
+            client.check_metrics(),
+            CheckMetrics {
+                upstream: 2,
+                coalesced: 0,
+                cached: 0
+            }
+        );
     }
 }
@@ -0,0 +1,215 @@
+use super::openfga::*;
+use super::UrkelError;
+use rocket::futures::{
+    stream::{self, BoxStream},
+    Future, StreamExt, TryStreamExt,
+};
+
+/// The response of a paginated call.
+pub trait Page: Send + 'static {
+    type Item: Send + 'static;
+
+    /// The continuation token of the following page, if there is one.
+    fn next_page(&self) -> Option<&str>;
+
+    fn into_items(self) -> Vec<Self::Item>;
+}
+
+fn non_empty(continuation_token: &str) -> Option<&str> {
+    Some(continuation_token).filter(|token| !token.is_empty())
+}
+
+impl Page for ListStoresResponse {
+    type Item = Store;
+
+    fn next_page(&self) -> Option<&str> {
+        non_empty(&self.continuation_token)
+    }
+
+    fn into_items(self) -> Vec<Store> {
+        self.stores
+    }
+}
+
+impl Page for ReadAuthorizationModelsResponse {
+    type Item = AuthorizationModel;
+
+    fn next_page(&self) -> Option<&str> {
+        non_empty(&self.continuation_token)
+    }
+
+    fn into_items(self) -> Vec<AuthorizationModel> {
+        self.authorization_models
+    }
+}
+
+impl Page for ReadResponse {
+    type Item = Tuple;
+
+    fn next_page(&self) -> Option<&str> {
+        non_empty(&self.continuation_token)
+    }
+
+    fn into_items(self) -> Vec<Tuple> {
+        self.tuples
+    }
+}
+
+impl Page for ReadChangesResponse {
+    type Item = TupleChange;
+
+    /// OpenFGA keeps answering with a token after the last change, to be polled for new ones, so
+    /// the changes end with the first empty page.
+    fn next_page(&self) -> Option<&str> {
+        if self.changes.is_empty() {
+            return None;
+        }
+        non_empty(&self.continuation_token)
+    }
+
+    fn into_items(self) -> Vec<TupleChange> {
+        self.changes
+    }
+}
+
+/// The pages following `continuation_token`, each fetched when the previous one was consumed.
+pub fn pages<P, F, Fut>(
+    continuation_token: Option<String>,
+    mut fetch: F,
+) -> BoxStream<'static, Result<P, UrkelError>>
+where
+    P: Page,
+    F: FnMut(Option<String>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<P, UrkelError>> + Send + 'static,
+{
+    stream::try_unfold(Some(continuation_token), move |continuation_token| {
+        let page = continuation_token.map(&mut fetch);
+        async move {
+            let page = match page {
+                Some(page) => page.await?,
+                None => return Ok(None),
+            };
+            let next = page.next_page().map(|token| Some(token.to_owned()));
+            Ok(Some((page, next)))
+        }
+    })
+    .boxed()
+}
+
+/// The items of every page, in order.
+pub fn items<P: Page>(
+    pages: BoxStream<'static, Result<P, UrkelError>>,
+) -> BoxStream<'static, Result<P::Item, UrkelError>> {
+    pages
+        .map_ok(|page| stream::iter(page.into_items().into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ErrorCode;
+    use rocket::futures::future;
+    use std::sync::{Arc, Mutex};
+
+    /// The continuation tokens pages were fetched with.
+    type Tokens = Arc<Mutex<Vec<Option<String>>>>;
+
+    fn store(id: &str) -> Store {
+        Store {
+            id: id.to_owned(),
+            ..Store::default()
+        }
+    }
+
+    fn stores(ids: &[&str], continuation_token: &str) -> ListStoresResponse {
+        ListStoresResponse {
+            stores: ids.iter().map(|id| store(id)).collect(),
+            continuation_token: continuation_token.to_owned(),
+        }
+    }
+
+    /// Answers with `responses` in turn, recording the continuation tokens it was called with.
+    fn fetch<P: Page>(
+        responses: Vec<Result<P, UrkelError>>,
+    ) -> (
+        impl FnMut(Option<String>) -> future::Ready<Result<P, UrkelError>> + Send + 'static,
+        Tokens,
+    ) {
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let received = tokens.clone();
+        let mut responses = responses.into_iter();
+        let fetch = move |continuation_token| {
+            received.lock().unwrap().push(continuation_token);
+            future::ready(responses.next().expect("no page left"))
+        };
+        (fetch, tokens)
+    }
+
+    #[rocket::async_test]
+    async fn follows_continuation_tokens_until_the_last_page() {
+        let (fetch, tokens) = fetch(vec![
+            Ok(stores(&["a", "b"], "second")),
+            Ok(stores(&["c"], "third")),
+            Ok(stores(&[], "")),
+        ]);
+
+        let ids: Vec<_> = items(pages(Some("first".to_owned()), fetch))
+            .map_ok(|store| store.id)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert_eq!(
+            *tokens.lock().unwrap(),
+            vec![
+                Some("first".to_owned()),
+                Some("second".to_owned()),
+                Some("third".to_owned()),
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn fetches_pages_as_they_are_consumed() {
+        let (fetch, tokens) = fetch(vec![Ok(stores(&["a"], "second")), Ok(stores(&["b"], ""))]);
+        let mut pages = pages(None, fetch);
+        assert!(tokens.lock().unwrap().is_empty());
+
+        pages.next().await.unwrap().unwrap();
+        assert_eq!(*tokens.lock().unwrap(), vec![None]);
+        pages.next().await.unwrap().unwrap();
+        assert!(pages.next().await.is_none());
+        assert_eq!(tokens.lock().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn ends_changes_with_the_first_empty_page() {
+        let change = TupleChange::default();
+        let (fetch, tokens) = fetch(vec![
+            Ok(ReadChangesResponse {
+                changes: vec![change.clone(), change],
+                continuation_token: "second".to_owned(),
+            }),
+            Ok(ReadChangesResponse {
+                changes: Vec::new(),
+                continuation_token: "second".to_owned(),
+            }),
+        ]);
+
+        let changes: Vec<_> = items(pages(None, fetch)).try_collect().await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(tokens.lock().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn stops_at_the_first_error() {
+        let error = UrkelError::validation(ErrorCode::InvalidContinuationToken, "boom");
+        let (fetch, tokens) = fetch(vec![Ok(stores(&["a"], "second")), Err(error.clone())]);
+
+        let results: Vec<_> = items(pages(None, fetch)).collect().await;
+        assert_eq!(results, vec![Ok(store("a")), Err(error)]);
+        assert_eq!(tokens.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,894 @@
+use super::backend::{BackendError, OpenFgaBackend};
+use super::openfga::*;
+use super::{Credentials, UrkelError};
+use crate::config::OpenFgaConfig;
+use crate::models::{ErrorCode, InternalErrorCode, NotFoundErrorCode};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use rocket::futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::time::Duration;
+use url::Url;
+
+/// Reaches OpenFGA through its HTTP API, for deployments which only expose that port.
+///
+/// OpenFGA's JSON differs from the serde shape of the generated types in a few places, which are
+/// rewritten on the way in: enums are sent by name, oneofs are flattened into their parent, and
+/// usersets use snake case.
+#[derive(Clone, Debug)]
+pub struct RestBackend {
+    base_url: Url,
+    http: reqwest::Client,
+    credentials: Credentials,
+}
+
+impl RestBackend {
+    pub fn new(
+        config: &OpenFgaConfig,
+        credentials: Credentials,
+    ) -> Result<RestBackend, Box<dyn Error>> {
+        let mut http = reqwest::Client::builder().connect_timeout(config.timeouts.connect());
+        if let Some(tls) = &config.tls {
+            if let Some(ca_cert_path) = &tls.ca_cert_path {
+                let ca_cert = std::fs::read(ca_cert_path)?;
+                http = http.add_root_certificate(reqwest::Certificate::from_pem(&ca_cert)?);
+            }
+            if let (Some(cert_path), Some(key_path)) = (&tls.client_cert_path, &tls.client_key_path)
+            {
+                let cert = std::fs::read(cert_path)?;
+                let key = std::fs::read(key_path)?;
+                http = http.identity(reqwest::Identity::from_pkcs8_pem(&cert, &key)?);
+            }
+        }
+
+        Ok(RestBackend {
+            base_url: Url::parse(&config.api_url)?,
+            http: http.build()?,
+            credentials,
+        })
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.base_url.clone();
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+        self.http.request(method, url)
+    }
+
+    /// Sends a request and reads the JSON answer, after `rewrite` adapted it to the generated
+    /// types.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+        timeout: Duration,
+        rewrite: fn(&mut Value),
+    ) -> Result<T, BackendError> {
+        let response = self.send_request(request, timeout).await?;
+
+        // Calls without a result, like deletions, answer with an empty body.
+        let bytes = response.bytes().await?;
+        let mut body = if bytes.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_slice(&bytes).map_err(unexpected_response)?
+        };
+        rewrite(&mut body);
+        serde_json::from_value(body).map_err(unexpected_response)
+    }
+
+    /// Sends a request, turning answers other than a success into errors.
+    async fn send_request(
+        &self,
+        request: RequestBuilder,
+        timeout: Duration,
+    ) -> Result<Response, BackendError> {
+        let mut request = request.timeout(timeout);
+        if let Some(authorization) = self.credentials.required_authorization()? {
+            request = request.header(reqwest::header::AUTHORIZATION, authorization.as_bytes());
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.json().await.unwrap_or(Value::Null);
+            return Err(BackendError::new(error_from_response(status, &body)));
+        }
+        Ok(response)
+    }
+}
+
+/// Splits a streamed body into its lines.
+fn lines(
+    body: impl Stream<Item = reqwest::Result<bytes::Bytes>> + Send + 'static,
+) -> impl Stream<Item = Result<Vec<u8>, BackendError>> {
+    stream::unfold(
+        (body.boxed(), Vec::new()),
+        |(mut body, mut buffer)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line = buffer.drain(..=end).collect();
+                    return Some((Ok(line), (body, buffer)));
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(error)) => return Some((Err(error.into()), (body, Vec::new()))),
+                    None if buffer.is_empty() => return None,
+                    None => return Some((Ok(std::mem::take(&mut buffer)), (body, buffer))),
+                }
+            }
+        },
+    )
+}
+
+/// Decodes a line of a streamed answer, either `{ "result": ... }` or `{ "error": ... }`.
+fn streamed_result<T: DeserializeOwned>(line: &[u8]) -> Result<T, BackendError> {
+    let mut line: Value = serde_json::from_slice(line).map_err(unexpected_response)?;
+    if let Some(error) = line.get("error") {
+        let status = error["http_code"]
+            .as_u64()
+            .and_then(|code| StatusCode::from_u16(u16::try_from(code).ok()?).ok())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(BackendError::new(error_from_response(status, error)));
+    }
+    serde_json::from_value(line["result"].take()).map_err(unexpected_response)
+}
+
+fn unexpected_response(error: serde_json::Error) -> BackendError {
+    BackendError::new(UrkelError::internal(
+        InternalErrorCode::InternalError,
+        format!("Unexpected response from OpenFGA: {}", error),
+    ))
+}
+
+/// The body of a request, without the ids which go in the path and without unset fields.
+fn body<T: Serialize>(request: &T) -> Value {
+    let mut body = serde_json::to_value(request).unwrap_or(Value::Null);
+    if let Value::Object(fields) = &mut body {
+        fields.remove("store_id");
+    }
+    remove_nulls(&mut body);
+    body
+}
+
+/// The body of a request which also names its authorization model in the path.
+fn body_without_model<T: Serialize>(request: &T) -> Value {
+    let mut body = body(request);
+    if let Value::Object(fields) = &mut body {
+        fields.remove("authorization_model_id");
+    }
+    body
+}
+
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|_, field| !field.is_null());
+            fields.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+fn query(page_size: Option<i32>, continuation_token: &str) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(page_size) = page_size {
+        query.push(("page_size", page_size.to_string()));
+    }
+    if !continuation_token.is_empty() {
+        query.push(("continuation_token", continuation_token.to_owned()));
+    }
+    query
+}
+
+/// Decodes OpenFGA's `{ code, message }` error bodies, falling back on the HTTP status.
+fn error_from_response(status: StatusCode, body: &Value) -> UrkelError {
+    let message = body["message"]
+        .as_str()
+        .or_else(|| status.canonical_reason())
+        .unwrap_or_default()
+        .to_owned();
+    let code = body["code"].clone();
+    if let Ok(code) = serde_json::from_value::<ErrorCode>(code.clone()) {
+        return UrkelError::validation(code, message);
+    }
+    if let Ok(code) = serde_json::from_value::<NotFoundErrorCode>(code.clone()) {
+        return UrkelError::not_found(code, message);
+    }
+    if let Ok(code) = serde_json::from_value::<InternalErrorCode>(code) {
+        return UrkelError::internal(code, message);
+    }
+
+    match status {
+        StatusCode::BAD_REQUEST => UrkelError::validation(ErrorCode::ValidationError, message),
+        StatusCode::NOT_FOUND => {
+            UrkelError::not_found(NotFoundErrorCode::UndefinedEndpoint, message)
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => UrkelError::internal(
+            InternalErrorCode::InternalError,
+            format!("OpenFGA rejected the configured credentials: {}", message),
+        ),
+        StatusCode::CONFLICT => UrkelError::internal(InternalErrorCode::AlreadyExists, message),
+        StatusCode::TOO_MANY_REQUESTS => {
+            UrkelError::internal(InternalErrorCode::ResourceExhausted, message)
+        }
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => {
+            UrkelError::internal(InternalErrorCode::Unavailable, message)
+        }
+        StatusCode::GATEWAY_TIMEOUT => {
+            UrkelError::internal(InternalErrorCode::DeadlineExceeded, message)
+        }
+        _ => UrkelError::internal(InternalErrorCode::InternalError, message),
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(error: reqwest::Error) -> BackendError {
+        if error.is_connect() {
+            BackendError::unsent(UrkelError::internal(
+                InternalErrorCode::Unavailable,
+                error.to_string(),
+            ))
+        } else if error.is_timeout() {
+            BackendError::new(UrkelError::internal(
+                InternalErrorCode::DeadlineExceeded,
+                error.to_string(),
+            ))
+        } else {
+            BackendError::new(UrkelError::internal(
+                InternalErrorCode::InternalError,
+                error.to_string(),
+            ))
+        }
+    }
+}
+
+fn unchanged(_: &mut Value) {}
+
+/// Tuple operations are sent by name, e.g. `TUPLE_OPERATION_WRITE`.
+fn rewrite_changes(response: &mut Value) {
+    if let Some(changes) = response["changes"].as_array_mut() {
+        for change in changes {
+            let operation = change["operation"]
+                .as_str()
+                .and_then(TupleOperation::from_str_name);
+            if let Some(operation) = operation {
+                change["operation"] = json!(operation as i32);
+            }
+        }
+    }
+}
+
+const USERSET_FIELDS: [(&str, &str); 2] = [
+    ("computed_userset", "computedUserset"),
+    ("tuple_to_userset", "tupleToUserset"),
+];
+
+/// Usersets of authorization models are read with camel case field names.
+fn rewrite_models(response: &mut Value) {
+    rename_fields(response, &USERSET_FIELDS);
+}
+
+/// The body of a model written to OpenFGA, whose usersets use snake case.
+fn model_body(request: &WriteAuthorizationModelRequest) -> Value {
+    let mut body = body(request);
+    rename_fields(
+        &mut body,
+        &USERSET_FIELDS.map(|(snake, camel)| (camel, snake)),
+    );
+    body
+}
+
+fn rename_fields(value: &mut Value, renames: &[(&str, &str)]) {
+    match value {
+        Value::Object(fields) => {
+            for (from, to) in renames {
+                if let Some(field) = fields.remove(*from) {
+                    fields.insert((*to).to_owned(), field);
+                }
+            }
+            fields
+                .values_mut()
+                .for_each(|field| rename_fields(field, renames));
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| rename_fields(item, renames)),
+        _ => {}
+    }
+}
+
+fn rewrite_expand(response: &mut Value) {
+    rewrite_node(&mut response["tree"]["root"]);
+}
+
+/// Moves the oneof of a userset tree node, e.g. `{ "name", "union": ... }`, under `value` as an
+/// externally tagged enum.
+fn rewrite_node(node: &mut Value) {
+    let Value::Object(fields) = node else {
+        return;
+    };
+    for (key, variant) in [
+        ("leaf", "Leaf"),
+        ("difference", "Difference"),
+        ("union", "Union"),
+        ("intersection", "Intersection"),
+    ] {
+        if let Some(mut value) = fields.remove(key) {
+            match variant {
+                "Leaf" => rewrite_leaf(&mut value),
+                "Difference" => {
+                    rewrite_node(&mut value["base"]);
+                    rewrite_node(&mut value["subtract"]);
+                }
+                _ => {
+                    if let Some(nodes) = value["nodes"].as_array_mut() {
+                        nodes.iter_mut().for_each(rewrite_node);
+                    }
+                }
+            }
+            fields.insert("value".to_owned(), tagged(variant, value));
+        }
+    }
+}
+
+fn rewrite_leaf(leaf: &mut Value) {
+    let Value::Object(fields) = leaf else {
+        return;
+    };
+    for (key, variant) in [
+        ("users", "Users"),
+        ("computed", "Computed"),
+        ("tuple_to_userset", "TupleToUserset"),
+        ("tupleToUserset", "TupleToUserset"),
+    ] {
+        if let Some(value) = fields.remove(key) {
+            fields.insert("value".to_owned(), tagged(variant, value));
+        }
+    }
+}
+
+fn tagged(variant: &str, value: Value) -> Value {
+    let mut tagged = Map::new();
+    tagged.insert(variant.to_owned(), value);
+    Value::Object(tagged)
+}
+
+#[rocket::async_trait]
+impl OpenFgaBackend for RestBackend {
+    async fn get_store(
+        &self,
+        request: GetStoreRequest,
+        timeout: Duration,
+    ) -> Result<GetStoreResponse, BackendError> {
+        let http_request = self.request(Method::GET, &["stores", &request.store_id]);
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn list_stores(
+        &self,
+        request: ListStoresRequest,
+        timeout: Duration,
+    ) -> Result<ListStoresResponse, BackendError> {
+        let http_request = self
+            .request(Method::GET, &["stores"])
+            .query(&query(request.page_size, &request.continuation_token));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn create_store(
+        &self,
+        request: CreateStoreRequest,
+        timeout: Duration,
+    ) -> Result<CreateStoreResponse, BackendError> {
+        let http_request = self
+            .request(Method::POST, &["stores"])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn update_store(
+        &self,
+        request: UpdateStoreRequest,
+        timeout: Duration,
+    ) -> Result<UpdateStoreResponse, BackendError> {
+        let http_request = self
+            .request(Method::PATCH, &["stores", &request.store_id])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn delete_store(
+        &self,
+        request: DeleteStoreRequest,
+        timeout: Duration,
+    ) -> Result<DeleteStoreResponse, BackendError> {
+        let http_request = self.request(Method::DELETE, &["stores", &request.store_id]);
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn read_authorization_models(
+        &self,
+        request: ReadAuthorizationModelsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelsResponse, BackendError> {
+        let store_id = request.store_id.unwrap_or_default();
+        let http_request = self
+            .request(Method::GET, &["stores", &store_id, "authorization-models"])
+            .query(&query(request.page_size, &request.continuation_token));
+        self.send(http_request, timeout, rewrite_models).await
+    }
+
+    async fn write_authorization_model(
+        &self,
+        request: WriteAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<WriteAuthorizationModelResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "authorization-models"])
+            .json(&model_body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn read_authorization_model(
+        &self,
+        request: ReadAuthorizationModelRequest,
+        timeout: Duration,
+    ) -> Result<ReadAuthorizationModelResponse, BackendError> {
+        let store_id = request.store_id.unwrap_or_default();
+        let http_request = self.request(
+            Method::GET,
+            &["stores", &store_id, "authorization-models", &request.id],
+        );
+        self.send(http_request, timeout, rewrite_models).await
+    }
+
+    async fn read_changes(
+        &self,
+        request: ReadChangesRequest,
+        timeout: Duration,
+    ) -> Result<ReadChangesResponse, BackendError> {
+        let store_id = request.store_id.unwrap_or_default();
+        let mut query = query(request.page_size, &request.continuation_token);
+        if !request.r#type.is_empty() {
+            query.push(("type", request.r#type));
+        }
+        let http_request = self
+            .request(Method::GET, &["stores", &store_id, "changes"])
+            .query(&query);
+        self.send(http_request, timeout, rewrite_changes).await
+    }
+
+    async fn read(
+        &self,
+        request: ReadRequest,
+        timeout: Duration,
+    ) -> Result<ReadResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "read"])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn write(
+        &self,
+        request: WriteRequest,
+        timeout: Duration,
+    ) -> Result<WriteResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "write"])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn check(
+        &self,
+        request: CheckRequest,
+        timeout: Duration,
+    ) -> Result<CheckResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "check"])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn expand(
+        &self,
+        request: ExpandRequest,
+        timeout: Duration,
+    ) -> Result<ExpandResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "expand"])
+            .json(&body(&request));
+        self.send(http_request, timeout, rewrite_expand).await
+    }
+
+    async fn list_objects(
+        &self,
+        request: ListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<ListObjectsResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(Method::POST, &["stores", &store_id, "list-objects"])
+            .json(&body(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn streamed_list_objects(
+        &self,
+        request: StreamedListObjectsRequest,
+        timeout: Duration,
+    ) -> Result<BoxStream<'static, Result<StreamedListObjectsResponse, BackendError>>, BackendError>
+    {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(
+                Method::POST,
+                &["stores", &store_id, "streamed-list-objects"],
+            )
+            .json(&body(&request));
+        let response = self.send_request(http_request, timeout).await?;
+        Ok(lines(response.bytes_stream())
+            .filter(|line| {
+                let blank = matches!(line, Ok(line) if line.trim_ascii().is_empty());
+                async move { !blank }
+            })
+            .map(|line| line.and_then(|line| streamed_result(&line)))
+            .boxed())
+    }
+
+    async fn read_assertions(
+        &self,
+        request: ReadAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<ReadAssertionsResponse, BackendError> {
+        let http_request = self.request(
+            Method::GET,
+            &[
+                "stores",
+                &request.store_id,
+                "assertions",
+                &request.authorization_model_id,
+            ],
+        );
+        self.send(http_request, timeout, unchanged).await
+    }
+
+    async fn write_assertions(
+        &self,
+        request: WriteAssertionsRequest,
+        timeout: Duration,
+    ) -> Result<WriteAssertionsResponse, BackendError> {
+        let store_id = request.store_id.clone().unwrap_or_default();
+        let http_request = self
+            .request(
+                Method::PUT,
+                &[
+                    "stores",
+                    &store_id,
+                    "assertions",
+                    &request.authorization_model_id,
+                ],
+            )
+            .json(&body_without_model(&request));
+        self.send(http_request, timeout, unchanged).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CredentialsConfig, CredentialsMethod, Transport};
+    use rocket::tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use std::sync::{Arc, Mutex};
+
+    const STORE_ID: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+
+    /// An OpenFGA stand-in answering its requests with `responses` in turn. Returns its URL and
+    /// the requests it received.
+    async fn openfga_endpoint(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                received.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} OpenFGA\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length || read == 0 {
+                    return text;
+                }
+            }
+        }
+    }
+
+    fn backend(url: &str, credentials: Credentials) -> RestBackend {
+        let config = OpenFgaConfig {
+            api_url: url.to_owned(),
+            transport: Transport::Http,
+            ..OpenFgaConfig::default()
+        };
+        RestBackend::new(&config, credentials).unwrap()
+    }
+
+    fn check_request() -> CheckRequest {
+        CheckRequest {
+            store_id: Some(STORE_ID.to_owned()),
+            tuple_key: Some(TupleKey {
+                object: Some("doc:a".to_owned()),
+                relation: Some("viewer".to_owned()),
+                user: Some("user:a".to_owned()),
+            }),
+            ..CheckRequest::default()
+        }
+    }
+
+    #[rocket::async_test]
+    async fn sends_the_credentials_and_reads_the_answer() {
+        let (url, requests) =
+            openfga_endpoint(vec![(200, r#"{"allowed":true,"resolution":""}"#)]).await;
+        let credentials = Credentials::from_config(&CredentialsConfig {
+            method: CredentialsMethod::ApiToken,
+            api_token: Some("token".to_owned()),
+            ..CredentialsConfig::default()
+        })
+        .unwrap();
+
+        let response = backend(&url, credentials)
+            .check(check_request(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(response.allowed);
+
+        let request = requests.lock().unwrap()[0].clone();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with(&format!("POST /stores/{}/check ", STORE_ID)));
+        assert!(head
+            .lines()
+            .any(|line| line.eq_ignore_ascii_case("authorization: Bearer token")));
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["tuple_key"]["object"], "doc:a");
+        assert!(body.get("store_id").is_none());
+        assert!(body.get("authorization_model_id").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn maps_error_answers_to_errors() {
+        let credentials =
+            |message: &str| format!("OpenFGA rejected the configured credentials: {}", message);
+        let cases = [
+            (
+                400,
+                r#"{"code":"invalid_check_input","message":"bad"}"#,
+                UrkelError::validation(ErrorCode::InvalidCheckInput, "bad"),
+            ),
+            (
+                404,
+                r#"{"code":"store_id_not_found","message":"gone"}"#,
+                UrkelError::not_found(NotFoundErrorCode::StoreIdNotFound, "gone"),
+            ),
+            (
+                500,
+                r#"{"code":"deadline_exceeded","message":"slow"}"#,
+                UrkelError::internal(InternalErrorCode::DeadlineExceeded, "slow"),
+            ),
+            (
+                400,
+                "",
+                UrkelError::validation(ErrorCode::ValidationError, "Bad Request"),
+            ),
+            (
+                401,
+                "",
+                UrkelError::internal(
+                    InternalErrorCode::InternalError,
+                    credentials("Unauthorized"),
+                ),
+            ),
+            (
+                403,
+                r#"{"message":"no"}"#,
+                UrkelError::internal(InternalErrorCode::InternalError, credentials("no")),
+            ),
+            (
+                404,
+                "",
+                UrkelError::not_found(NotFoundErrorCode::UndefinedEndpoint, "Not Found"),
+            ),
+            (
+                409,
+                "",
+                UrkelError::internal(InternalErrorCode::AlreadyExists, "Conflict"),
+            ),
+            (
+                429,
+                "",
+                UrkelError::internal(InternalErrorCode::ResourceExhausted, "Too Many Requests"),
+            ),
+            (
+                500,
+                "not json",
+                UrkelError::internal(InternalErrorCode::InternalError, "Internal Server Error"),
+            ),
+            (
+                502,
+                "",
+                UrkelError::internal(InternalErrorCode::Unavailable, "Bad Gateway"),
+            ),
+            (
+                503,
+                "",
+                UrkelError::internal(InternalErrorCode::Unavailable, "Service Unavailable"),
+            ),
+            (
+                504,
+                "",
+                UrkelError::internal(InternalErrorCode::DeadlineExceeded, "Gateway Timeout"),
+            ),
+        ];
+        let (url, _) = openfga_endpoint(
+            cases
+                .iter()
+                .map(|(status, body, _)| (*status, *body))
+                .collect(),
+        )
+        .await;
+        let backend = backend(&url, Credentials::None);
+
+        for (status, _, expected) in cases {
+            let error = backend
+                .check(check_request(), Duration::from_secs(5))
+                .await
+                .unwrap_err();
+            assert_eq!(error, BackendError::new(expected), "HTTP status {}", status);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn reports_unreachable_servers_as_unsent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let error = backend(&url, Credentials::None)
+            .check(check_request(), Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(error.unsent);
+        assert_eq!(error.error.http_status(), 503);
+    }
+
+    #[rocket::async_test]
+    async fn reports_timeouts_as_deadline_exceeded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+
+        let error = backend(&url, Credentials::None)
+            .check(check_request(), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(!error.unsent);
+        assert_eq!(error.error.http_status(), 504);
+    }
+
+    #[rocket::async_test]
+    async fn decodes_results_and_errors_of_streams() {
+        let (url, _) = openfga_endpoint(vec![(
+            200,
+            "{\"result\":{\"object\":\"doc:a\"}}\n\n\
+             {\"error\":{\"http_code\":404,\"code\":\"store_id_not_found\",\"message\":\"gone\"}}\n",
+        )])
+        .await;
+        let request = StreamedListObjectsRequest {
+            store_id: Some(STORE_ID.to_owned()),
+            ..StreamedListObjectsRequest::default()
+        };
+
+        let results: Vec<_> = backend(&url, Credentials::None)
+            .streamed_list_objects(request, Duration::from_secs(5))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(
+            results,
+            vec![
+                Ok(StreamedListObjectsResponse {
+                    object: "doc:a".to_owned()
+                }),
+                Err(BackendError::new(UrkelError::not_found(
+                    NotFoundErrorCode::StoreIdNotFound,
+                    "gone"
+                ))),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_expanded_trees_into_the_generated_types() {
+        let mut response = json!({
+            "tree": {"root": {"name": "doc:a#viewer", "difference": {
+                "base": {"name": "doc:a#editor", "leaf": {"users": {"users": ["user:a"]}}},
+                "subtract": {"name": "doc:a#blocked", "union": {"nodes": [
+                    {"name": "doc:a#blocked", "leaf": {"computed": {"userset": "doc:a#owner"}}}
+                ]}}
+            }}}
+        });
+        rewrite_expand(&mut response);
+        let response: ExpandResponse = serde_json::from_value(response).unwrap();
+        let root = response.tree.unwrap().root.unwrap();
+        let Some(userset_tree::node::Value::Difference(difference)) = root.value else {
+            panic!("expected a difference, got {:?}", root.value);
+        };
+        let base = difference.base.unwrap().value;
+        assert!(matches!(
+            base,
+            Some(userset_tree::node::Value::Leaf(userset_tree::Leaf {
+                value: Some(userset_tree::leaf::Value::Users(_)),
+            }))
+        ));
+        let Some(userset_tree::node::Value::Union(nodes)) = difference.subtract.unwrap().value
+        else {
+            panic!("expected a union to subtract");
+        };
+        assert!(matches!(
+            &nodes.nodes[0].value,
+            Some(userset_tree::node::Value::Leaf(userset_tree::Leaf {
+                value: Some(userset_tree::leaf::Value::Computed(_)),
+            }))
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+use super::backend::BackendError;
+use super::UrkelError;
+use crate::config::RetryConfig;
+use crate::models::InternalErrorCode;
+use rand::Rng;
+use std::time::Duration;
+
+/// Whether a call may be repeated after OpenFGA has possibly processed it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Idempotency {
+    /// Reads and upserts, repeated after any retryable failure.
+    Idempotent,
+    /// Calls that must not be applied twice, like writes or store creation. They are only
+    /// repeated when OpenFGA cannot have acted on them: the connection could not be established
+    /// or the call was rejected by rate limiting.
+    NonIdempotent,
+}
+
+/// Exponential backoff with jitter for calls failing with a transient error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub retryable_codes: Vec<InternalErrorCode>,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: config.max_attempts,
+            initial_backoff: config.initial_backoff(),
+            max_backoff: config.max_backoff(),
+            multiplier: config.multiplier,
+            jitter: config.jitter,
+            retryable_codes: config.retryable_codes.clone(),
+        }
+    }
+
+    /// A policy which never retries.
+    pub fn disabled() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::new(&RetryConfig::default())
+        }
+    }
+
+    /// How long to wait before repeating a call whose `attempt`-th try (counting from 1) failed
+    /// with `error`, or `None` when the failure should be returned.
+    pub fn backoff(
+        &self,
+        attempt: u32,
+        error: &BackendError,
+        idempotency: Idempotency,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable(error, idempotency) {
+            return None;
+        }
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = backoff * self.jitter * rand::thread_rng().gen::<f64>();
+        Some(Duration::from_secs_f64(backoff - jitter))
+    }
+
+    fn is_retryable(&self, error: &BackendError, idempotency: Idempotency) -> bool {
+        let code = match &error.error {
+            UrkelError::Internal(error) => error.code.unwrap_or_default(),
+            _ => return false,
+        };
+        if !self.retryable_codes.contains(&code) {
+            return false;
+        }
+        match idempotency {
+            Idempotency::Idempotent => true,
+            Idempotency::NonIdempotent => {
+                code == InternalErrorCode::ResourceExhausted || error.unsent
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ErrorCode;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            multiplier: 2.0,
+            jitter,
+            retryable_codes: vec![
+                InternalErrorCode::Unavailable,
+                InternalErrorCode::ResourceExhausted,
+            ],
+        }
+    }
+
+    fn failure(code: InternalErrorCode) -> BackendError {
+        BackendError::new(UrkelError::internal(code, "boom"))
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_maximum() {
+        let policy = policy(0.0);
+        let error = failure(InternalErrorCode::Unavailable);
+        let backoffs: Vec<_> = (1..=5)
+            .map(|attempt| policy.backoff(attempt, &error, Idempotency::Idempotent))
+            .collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(500)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn shortens_backoffs_by_at_most_the_jitter() {
+        let policy = policy(0.5);
+        let error = failure(InternalErrorCode::Unavailable);
+        for _ in 0..100 {
+            let backoff = policy.backoff(2, &error, Idempotency::Idempotent).unwrap();
+            assert!(backoff > Duration::from_millis(100) && backoff <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn retries_only_the_configured_codes() {
+        let policy = policy(0.0);
+        let validation =
+            BackendError::new(UrkelError::validation(ErrorCode::ValidationError, "boom"));
+        for error in [failure(InternalErrorCode::InternalError), validation] {
+            assert_eq!(policy.backoff(1, &error, Idempotency::Idempotent), None);
+        }
+        assert!(policy
+            .backoff(
+                1,
+                &failure(InternalErrorCode::ResourceExhausted),
+                Idempotency::Idempotent
+            )
+            .is_some());
+        assert_eq!(
+            RetryPolicy::disabled().backoff(
+                1,
+                &failure(InternalErrorCode::Unavailable),
+                Idempotency::Idempotent
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn repeats_writes_only_when_they_cannot_have_been_applied() {
+        let policy = policy(0.0);
+        let write = Idempotency::NonIdempotent;
+        assert_eq!(
+            policy.backoff(1, &failure(InternalErrorCode::Unavailable), write),
+            None
+        );
+        let unsent =
+            BackendError::unsent(UrkelError::internal(InternalErrorCode::Unavailable, "boom"));
+        assert!(policy.backoff(1, &unsent, write).is_some());
+        assert!(policy
+            .backoff(1, &failure(InternalErrorCode::ResourceExhausted), write)
+            .is_some());
+    }
+}
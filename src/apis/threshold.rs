@@ -0,0 +1,303 @@
+use super::UrkelError;
+use crate::models::ErrorCode;
+use rocket::futures::{
+    stream::{self, FuturesUnordered},
+    Future, StreamExt,
+};
+use std::collections::BTreeMap;
+
+/// The outcome of a check evaluated against a threshold. Checks are referred to by their index in
+/// the request.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ThresholdCheckResponse {
+    #[serde(rename = "allowed")]
+    pub allowed: bool,
+    #[serde(rename = "passed")]
+    pub passed: Vec<usize>,
+    #[serde(rename = "failed")]
+    pub failed: Vec<usize>,
+    /// Checks which were not needed to decide, either never sent or cancelled.
+    #[serde(rename = "skipped")]
+    pub skipped: Vec<usize>,
+    /// Errors of the failed checks which could not be evaluated.
+    #[serde(rename = "errors", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<usize, UrkelError>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// The sum of `weights`, failing when it does not fit in a weight.
+pub fn total_weight(weights: &[u64]) -> Result<u64, UrkelError> {
+    weights
+        .iter()
+        .try_fold(0u64, |total, weight| total.checked_add(*weight))
+        .ok_or_else(|| {
+            UrkelError::validation(
+                ErrorCode::ValidationError,
+                "The weights of the checks add up to more than the largest weight.",
+            )
+        })
+}
+
+/// Runs `check` for each of the `weights`, at most `max_concurrent_checks` at a time, until the
+/// weights of the passed checks reach `threshold` or the remaining checks can no longer make them
+/// reach it. Outstanding checks are then cancelled. Checks failing with an error count as failed,
+/// which can only deny.
+pub async fn evaluate<F, Fut>(
+    weights: &[u64],
+    threshold: u64,
+    max_concurrent_checks: usize,
+    check: F,
+) -> Result<ThresholdCheckResponse, UrkelError>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = Result<bool, UrkelError>>,
+{
+    let mut outcomes = vec![Outcome::Skipped; weights.len()];
+    let mut errors = BTreeMap::new();
+    // Neither can overflow, since they stay within the total weight.
+    let mut passed = 0;
+    let mut reachable = total_weight(weights)?;
+
+    let mut allowed = passed >= threshold;
+    if !allowed && reachable >= threshold {
+        let mut checks = stream::iter(0..weights.len())
+            .map(|index| {
+                let check = check(index);
+                async move { (index, check.await) }
+            })
+            .buffer_unordered(max_concurrent_checks.max(1));
+        while let Some((index, result)) = checks.next().await {
+            match result {
+                Ok(true) => {
+                    outcomes[index] = Outcome::Passed;
+                    passed += weights[index];
+                }
+                Ok(false) => {
+                    outcomes[index] = Outcome::Failed;
+                    reachable -= weights[index];
+                }
+                Err(error) => {
+                    outcomes[index] = Outcome::Failed;
+                    reachable -= weights[index];
+                    errors.insert(index, error);
+                }
+            }
+            if passed >= threshold {
+                allowed = true;
+                break;
+            }
+            if reachable < threshold {
+                break;
+            }
+        }
+    }
+
+    let with_outcome = |outcome| {
+        (0..outcomes.len())
+            .filter(|index| outcomes[*index] == outcome)
+            .collect()
+    };
+    Ok(ThresholdCheckResponse {
+        allowed,
+        passed: with_outcome(Outcome::Passed),
+        failed: with_outcome(Outcome::Failed),
+        skipped: with_outcome(Outcome::Skipped),
+        errors,
+    })
+}
+
+/// Whether at least `num` of `count` conditions hold, evaluating them all at once until their
+/// outcomes decide, then cancelling the rest. A condition failing with an error may hold or not,
+/// so the error is only returned when the other conditions leave the outcome undecided.
+pub async fn at_least<F, Fut>(count: usize, num: usize, evaluate: F) -> Result<bool, UrkelError>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = Result<bool, UrkelError>>,
+{
+    let mut outcomes = (0..count).map(evaluate).collect::<FuturesUnordered<_>>();
+    let (mut held, mut failed) = (0, 0);
+    let mut error = None;
+    loop {
+        if held >= num {
+            return Ok(true);
+        }
+        if failed + num > count {
+            return Ok(false);
+        }
+        match outcomes.next().await {
+            Some(Ok(true)) => held += 1,
+            Some(Ok(false)) => failed += 1,
+            Some(Err(condition_error)) => {
+                error.get_or_insert(condition_error);
+            }
+            None => break,
+        }
+    }
+    error.map_or(Ok(held >= num), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InternalErrorCode;
+    use rocket::futures::future;
+    use std::cell::RefCell;
+
+    #[derive(Clone, Copy)]
+    enum Answer {
+        Allow,
+        Deny,
+        Fail,
+        /// Never answers, until cancelled.
+        Hang,
+    }
+
+    async fn answer(answer: Answer) -> Result<bool, UrkelError> {
+        match answer {
+            Answer::Allow => Ok(true),
+            Answer::Deny => Ok(false),
+            Answer::Fail => Err(UrkelError::internal(
+                InternalErrorCode::Unavailable,
+                "OpenFGA is unavailable.",
+            )),
+            Answer::Hang => future::pending().await,
+        }
+    }
+
+    /// Evaluates checks answering `answers`, returning the response and the checks sent.
+    async fn evaluate_answers(
+        answers: &[Answer],
+        weights: &[u64],
+        threshold: u64,
+        max_concurrent_checks: usize,
+    ) -> (ThresholdCheckResponse, Vec<usize>) {
+        let sent = RefCell::new(Vec::new());
+        let response = evaluate(weights, threshold, max_concurrent_checks, |index| {
+            sent.borrow_mut().push(index);
+            answer(answers[index])
+        })
+        .await
+        .unwrap();
+        (response, sent.into_inner())
+    }
+
+    #[rocket::async_test]
+    async fn needs_every_check_for_a_quorum_of_all() {
+        use Answer::*;
+        let (response, sent) = evaluate_answers(&[Allow, Allow, Allow], &[1, 1, 1], 3, 1).await;
+
+        assert!(response.allowed);
+        assert_eq!(response.passed, vec![0, 1, 2]);
+        assert!(response.failed.is_empty() && response.skipped.is_empty());
+        assert_eq!(sent, vec![0, 1, 2]);
+    }
+
+    #[rocket::async_test]
+    async fn stops_at_the_first_denial_of_a_quorum_of_all() {
+        use Answer::*;
+        let (response, sent) = evaluate_answers(&[Deny, Allow, Allow], &[1, 1, 1], 3, 1).await;
+
+        assert!(!response.allowed);
+        assert_eq!(response.failed, vec![0]);
+        assert_eq!(response.skipped, vec![1, 2]);
+        assert_eq!(sent, vec![0]);
+    }
+
+    #[rocket::async_test]
+    async fn stops_once_enough_checks_are_allowed() {
+        use Answer::*;
+        let (response, sent) =
+            evaluate_answers(&[Allow, Deny, Allow, Allow], &[1, 1, 1, 1], 2, 1).await;
+
+        assert!(response.allowed);
+        assert_eq!(response.passed, vec![0, 2]);
+        assert_eq!(response.failed, vec![1]);
+        assert_eq!(response.skipped, vec![3]);
+        assert_eq!(sent, vec![0, 1, 2]);
+    }
+
+    #[rocket::async_test]
+    async fn reports_cancelled_checks_as_skipped() {
+        use Answer::*;
+        let (response, sent) = evaluate_answers(&[Hang, Allow, Hang], &[1, 1, 1], 1, 3).await;
+
+        assert!(response.allowed);
+        assert_eq!(response.passed, vec![1]);
+        assert_eq!(response.skipped, vec![0, 2]);
+        assert_eq!(sent, vec![0, 1, 2]);
+    }
+
+    #[rocket::async_test]
+    async fn counts_errors_as_failed_checks() {
+        use Answer::*;
+        let (response, _) = evaluate_answers(&[Fail, Allow], &[1, 1], 2, 1).await;
+
+        assert!(!response.allowed);
+        assert_eq!(response.failed, vec![0]);
+        assert_eq!(response.skipped, vec![1]);
+        assert_eq!(response.errors.keys().copied().collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[rocket::async_test]
+    async fn weighs_the_allowed_checks() {
+        use Answer::*;
+        let (allowed, _) = evaluate_answers(&[Deny, Allow, Allow], &[5, 3, 2], 5, 1).await;
+        let (denied, sent) = evaluate_answers(&[Allow, Deny, Allow], &[1, 5, 2], 4, 1).await;
+
+        assert!(allowed.allowed);
+        assert_eq!(allowed.passed, vec![1, 2]);
+        assert!(!denied.allowed);
+        assert_eq!(denied.skipped, vec![2]);
+        assert_eq!(sent, vec![0, 1]);
+    }
+
+    #[rocket::async_test]
+    async fn sends_no_check_when_the_threshold_is_out_of_reach() {
+        use Answer::*;
+        let (response, sent) = evaluate_answers(&[Allow, Allow], &[1, 1], 3, 2).await;
+
+        assert!(!response.allowed);
+        assert_eq!(response.skipped, vec![0, 1]);
+        assert!(sent.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn rejects_weights_adding_up_past_the_largest_weight() {
+        assert_eq!(total_weight(&[u64::MAX - 1, 1]).unwrap(), u64::MAX);
+        assert!(total_weight(&[u64::MAX, 1]).is_err());
+
+        let response = evaluate(&[u64::MAX, 1], 1, 1, |_| answer(Answer::Allow)).await;
+
+        assert!(matches!(response, Err(UrkelError::Validation(_))));
+    }
+
+    async fn at_least_answers(answers: &[Answer], num: usize) -> Result<bool, UrkelError> {
+        at_least(answers.len(), num, |index| answer(answers[index])).await
+    }
+
+    #[rocket::async_test]
+    async fn decides_at_least_from_the_conditions_without_errors() {
+        use Answer::*;
+        assert_eq!(at_least_answers(&[Fail, Deny], 2).await, Ok(false));
+        assert_eq!(at_least_answers(&[Fail, Allow], 1).await, Ok(true));
+        assert_eq!(at_least_answers(&[Allow, Fail, Allow], 2).await, Ok(true));
+        assert_eq!(at_least_answers(&[Deny, Fail, Deny], 2).await, Ok(false));
+        assert_eq!(at_least_answers(&[Hang, Allow], 1).await, Ok(true));
+        assert_eq!(at_least_answers(&[Deny, Hang], 2).await, Ok(false));
+        assert_eq!(at_least_answers(&[], 0).await, Ok(true));
+    }
+
+    #[rocket::async_test]
+    async fn returns_errors_which_leave_at_least_undecided() {
+        use Answer::*;
+        assert!(at_least_answers(&[Allow, Fail], 2).await.is_err());
+        assert!(at_least_answers(&[Fail, Deny], 1).await.is_err());
+        assert!(at_least_answers(&[Deny, Fail, Allow], 2).await.is_err());
+    }
+}
@@ -0,0 +1,654 @@
+use super::openfga::{ReadChangesResponse, TupleChange};
+use super::{UrkelClient, UrkelError};
+use crate::models::InternalErrorCode;
+use rocket::futures::{stream::BoxStream, StreamExt};
+use rocket::tokio::{
+    self,
+    sync::{
+        broadcast::{self, error::RecvError, error::TryRecvError},
+        Mutex,
+    },
+    task::JoinHandle,
+};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A change of a watched store.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoreChange {
+    #[serde(rename = "store_id")]
+    pub store_id: String,
+    #[serde(rename = "change")]
+    pub change: TupleChange,
+    /// Token following the page of the change, which resumes the changes after that page.
+    #[serde(rename = "continuation_token")]
+    pub continuation_token: String,
+}
+
+/// Polls the changes of the configured stores in the background, forgetting their cached
+/// decisions when they change and publishing each change to the subscribers. This keeps the
+/// caches of Urkel instances sharing stores consistent with each other's writes. The polling
+/// stops when the watcher is dropped.
+#[derive(Debug)]
+pub struct ChangeWatcher {
+    stores: HashSet<String>,
+    changes: broadcast::Sender<StoreChange>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ChangeWatcher {
+    /// Starts polling the stores of `openfga.watcher`, from the continuation tokens persisted at
+    /// its `state_path`. Must be called within a Tokio runtime.
+    pub async fn spawn(client: &UrkelClient) -> Result<ChangeWatcher, io::Error> {
+        let config = &client.config.watcher;
+        let stores = if config.stores.is_empty() {
+            client.config.store_id.iter().cloned().collect()
+        } else {
+            config.stores.clone()
+        };
+        let tokens = Arc::new(Tokens::load(config.state_path.clone()).await?);
+        let (changes, _) = broadcast::channel(config.channel_capacity);
+
+        let tasks = stores
+            .iter()
+            .cloned()
+            .map(|store_id| {
+                let poller = Poller {
+                    client: client.clone(),
+                    store_id,
+                    tokens: tokens.clone(),
+                    changes: changes.clone(),
+                };
+                tokio::spawn(poller.run())
+            })
+            .collect();
+        Ok(ChangeWatcher {
+            stores: stores.into_iter().collect(),
+            changes,
+            tasks,
+        })
+    }
+
+    /// The stores whose changes are published.
+    pub fn stores(&self) -> impl Iterator<Item = &String> {
+        self.stores.iter()
+    }
+
+    /// Receives the changes published from now on. A subscriber lagging more than
+    /// `channel_capacity` changes behind misses the older ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<StoreChange> {
+        self.changes.subscribe()
+    }
+
+    /// The pages of changes of a store to objects of `type`, as published from now on, or `None`
+    /// when the store is not watched. Changes from `continuation_token` on are first read from
+    /// OpenFGA, which is also how the changes missed by lagging behind are caught up with. Those
+    /// reads are not filtered by type, so that every continuation token returned resumes the
+    /// changes of any type. The stream ends after the first error.
+    pub fn changes(
+        &self,
+        client: &UrkelClient,
+        store_id: &str,
+        r#type: Option<&str>,
+        page_size: Option<i32>,
+        continuation_token: Option<&str>,
+    ) -> Option<BoxStream<'static, Result<ReadChangesResponse, UrkelError>>> {
+        if !self.stores.contains(store_id) {
+            return None;
+        }
+        // Subscribed before catching up, so that no change is published in between.
+        let follower = Follower {
+            client: client.clone(),
+            store_id: store_id.to_owned(),
+            r#type: r#type.map(str::to_owned),
+            page_size,
+            changes: self.subscribe(),
+            next: None,
+            catch_up: continuation_token.map(str::to_owned),
+            continuation_token: continuation_token.map(str::to_owned),
+            caught_up_to: None,
+        };
+        let pages = rocket::futures::stream::try_unfold(follower, |mut follower| async move {
+            let page = follower.next_page().await?;
+            Ok(page.map(|page| (page, follower)))
+        });
+        Some(pages.boxed())
+    }
+}
+
+impl Drop for ChangeWatcher {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// When a change was made, to tell apart the changes already seen from the later ones.
+pub(super) fn timestamp(change: &TupleChange) -> Option<(i64, i32)> {
+    change
+        .timestamp
+        .as_ref()
+        .map(|timestamp| (timestamp.seconds, timestamp.nanos))
+}
+
+/// A subscriber to the changes of a single store, which reads the changes it cannot receive from
+/// OpenFGA.
+struct Follower {
+    client: UrkelClient,
+    store_id: String,
+    r#type: Option<String>,
+    page_size: Option<i32>,
+    changes: broadcast::Receiver<StoreChange>,
+    /// A change received while gathering the changes of the previous page.
+    next: Option<StoreChange>,
+    /// The token to read the changes from before receiving them again.
+    catch_up: Option<String>,
+    /// The token following the changes returned so far.
+    continuation_token: Option<String>,
+    /// The time of the latest change read from OpenFGA. The changes published until then were
+    /// already returned.
+    caught_up_to: Option<(i64, i32)>,
+}
+
+impl Follower {
+    fn wanted(&self, change: &TupleChange) -> bool {
+        let seen = match (self.caught_up_to, timestamp(change)) {
+            (Some(caught_up_to), Some(timestamp)) => timestamp <= caught_up_to,
+            _ => false,
+        };
+        let object = change
+            .tuple_key
+            .as_ref()
+            .and_then(|key| key.object.as_deref())
+            .unwrap_or("");
+        let object_type = object
+            .split_once(':')
+            .map_or(object, |(object_type, _)| object_type);
+        !seen
+            && self
+                .r#type
+                .as_ref()
+                .is_none_or(|wanted| wanted == object_type)
+    }
+
+    /// Reads the changes again from the token following those returned so far, after some
+    /// were missed.
+    fn lagged(&mut self) -> Result<(), UrkelError> {
+        match &self.continuation_token {
+            Some(continuation_token) => {
+                self.catch_up = Some(continuation_token.clone());
+                Ok(())
+            }
+            None => Err(UrkelError::internal(
+                InternalErrorCode::DataLoss,
+                "Changes were missed before any could be sent.",
+            )),
+        }
+    }
+
+    async fn next_page(&mut self) -> Result<Option<ReadChangesResponse>, UrkelError> {
+        loop {
+            if let Some(continuation_token) = self.catch_up.take() {
+                let page = self
+                    .client
+                    .read_changes(
+                        &self.store_id,
+                        None,
+                        self.page_size,
+                        Some(&continuation_token),
+                    )
+                    .await?
+                    .into_inner();
+                if page.changes.is_empty() {
+                    continue;
+                }
+                self.catch_up = Some(page.continuation_token.clone());
+                self.continuation_token = Some(page.continuation_token.clone());
+                let changes = page
+                    .changes
+                    .into_iter()
+                    .filter(|change| self.wanted(change))
+                    .collect::<Vec<_>>();
+                self.caught_up_to = changes
+                    .iter()
+                    .filter_map(timestamp)
+                    .max()
+                    .max(self.caught_up_to);
+                if !changes.is_empty() {
+                    return Ok(Some(ReadChangesResponse {
+                        changes,
+                        continuation_token: page.continuation_token,
+                    }));
+                }
+                continue;
+            }
+
+            let first = match self.next.take() {
+                Some(change) => change,
+                None => match self.changes.recv().await {
+                    Ok(change) => change,
+                    Err(RecvError::Lagged(_)) => {
+                        self.lagged()?;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(None),
+                },
+            };
+            if first.store_id != self.store_id {
+                continue;
+            }
+            // The changes of a page are published together and share its continuation token,
+            // which must only be returned after the last of them.
+            let continuation_token = first.continuation_token;
+            let mut changes = vec![first.change];
+            let lagged = loop {
+                match self.changes.try_recv() {
+                    Ok(change) if change.store_id != self.store_id => {}
+                    Ok(change) if change.continuation_token == continuation_token => {
+                        changes.push(change.change)
+                    }
+                    Ok(change) => {
+                        self.next = Some(change);
+                        break false;
+                    }
+                    Err(TryRecvError::Lagged(_)) => break true,
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break false,
+                }
+            };
+            if lagged {
+                // Some changes of the page were missed: the whole page is read again instead.
+                self.lagged()?;
+                continue;
+            }
+            self.continuation_token = Some(continuation_token.clone());
+            changes.retain(|change| self.wanted(change));
+            if !changes.is_empty() {
+                return Ok(Some(ReadChangesResponse {
+                    changes,
+                    continuation_token,
+                }));
+            }
+        }
+    }
+}
+
+/// Continuation tokens of the watched stores, written to `path` after each page of changes.
+#[derive(Debug)]
+struct Tokens {
+    path: Option<PathBuf>,
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Tokens {
+    async fn load(path: Option<PathBuf>) -> Result<Tokens, io::Error> {
+        let tokens = match &path {
+            Some(path) => match tokio::fs::read(path).await {
+                Ok(contents) => serde_json::from_slice(&contents)?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => HashMap::new(),
+                Err(error) => return Err(error),
+            },
+            None => HashMap::new(),
+        };
+        Ok(Tokens {
+            path,
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    async fn get(&self, store_id: &str) -> Option<String> {
+        self.tokens.lock().await.get(store_id).cloned()
+    }
+
+    /// Replaces the file at once, so that a crash never leaves it half written.
+    async fn set(&self, store_id: &str, continuation_token: &str) -> Result<(), io::Error> {
+        let mut tokens = self.tokens.lock().await;
+        tokens.insert(store_id.to_owned(), continuation_token.to_owned());
+        if let Some(path) = &self.path {
+            let staging = path.with_extension("tmp");
+            tokio::fs::write(&staging, serde_json::to_vec_pretty(&*tokens)?).await?;
+            tokio::fs::rename(&staging, path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The polling of a single store.
+struct Poller {
+    client: UrkelClient,
+    store_id: String,
+    tokens: Arc<Tokens>,
+    changes: broadcast::Sender<StoreChange>,
+}
+
+impl Poller {
+    /// Polls until aborted. Pages are read back to back while there are changes, then once per
+    /// poll interval. Failures are logged and retried after the interval.
+    async fn run(self) {
+        let poll_interval = self.client.config.watcher.poll_interval();
+        let mut continuation_token = loop {
+            match self.starting_token().await {
+                Ok(continuation_token) => break continuation_token,
+                Err(error) => {
+                    eprintln!(
+                        "Could not start watching store {}: {}",
+                        self.store_id, error
+                    );
+                    tokio::time::sleep(poll_interval).await;
+                }
+            }
+        };
+        loop {
+            match self.poll(&continuation_token).await {
+                Ok(Some(next)) => continuation_token = next,
+                Ok(None) => tokio::time::sleep(poll_interval).await,
+                Err(error) => {
+                    eprintln!(
+                        "Could not read the changes of store {}: {}",
+                        self.store_id, error
+                    );
+                    tokio::time::sleep(poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// The persisted token of the store, or else the one following its latest change.
+    async fn starting_token(&self) -> Result<String, UrkelError> {
+        if let Some(continuation_token) = self.tokens.get(&self.store_id).await {
+            return Ok(continuation_token);
+        }
+        let page_size = self.client.config.watcher.page_size;
+        let continuation_token = self
+            .client
+            .latest_changes_token(&self.store_id, None, page_size)
+            .await?;
+        if !continuation_token.is_empty() {
+            self.persist(&continuation_token).await;
+        }
+        Ok(continuation_token)
+    }
+
+    /// Publishes the changes following `continuation_token`, returning the token following
+    /// them, or `None` when there were none.
+    async fn poll(&self, continuation_token: &str) -> Result<Option<String>, UrkelError> {
+        let page_size = self.client.config.watcher.page_size;
+        let page = self
+            .client
+            .read_changes(&self.store_id, None, page_size, Some(continuation_token))
+            .await?
+            .into_inner();
+        if page.changes.is_empty() {
+            return Ok(None);
+        }
+
+        self.client.invalidate_cache(&self.store_id);
+        for change in page.changes {
+            // Sending only fails when nobody is subscribed.
+            let _ = self.changes.send(StoreChange {
+                store_id: self.store_id.clone(),
+                change,
+                continuation_token: page.continuation_token.clone(),
+            });
+        }
+        self.persist(&page.continuation_token).await;
+        Ok(Some(page.continuation_token))
+    }
+
+    async fn persist(&self, continuation_token: &str) {
+        if let Err(error) = self.tokens.set(&self.store_id, continuation_token).await {
+            eprintln!(
+                "Could not persist the continuation token of store {}: {}",
+                self.store_id, error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::{TupleKey, TupleOperation};
+    use crate::apis::tests::{changes_log, openfga, openfga_with, Calls, STORE_ID};
+    use crate::config::{OpenFgaConfig, WatcherConfig};
+    use prost_wkt_types::Timestamp;
+    use std::path::Path;
+    use std::sync::Mutex as SyncMutex;
+
+    /// The `index`-th change of a store, made at `index + 1` seconds.
+    fn change(object: &str, index: i64) -> TupleChange {
+        TupleChange {
+            tuple_key: Some(TupleKey {
+                object: Some(object.to_owned()),
+                relation: Some("viewer".to_owned()),
+                user: Some("user:a".to_owned()),
+            }),
+            operation: TupleOperation::Write as i32,
+            timestamp: Some(Timestamp {
+                seconds: index + 1,
+                nanos: 0,
+            }),
+        }
+    }
+
+    fn published(change: TupleChange, continuation_token: &str) -> StoreChange {
+        StoreChange {
+            store_id: STORE_ID.to_owned(),
+            change,
+            continuation_token: continuation_token.to_owned(),
+        }
+    }
+
+    fn watcher(capacity: usize) -> ChangeWatcher {
+        ChangeWatcher {
+            stores: HashSet::from([STORE_ID.to_owned()]),
+            changes: broadcast::channel(capacity).0,
+            tasks: Vec::new(),
+        }
+    }
+
+    fn objects(page: &ReadChangesResponse) -> Vec<&str> {
+        page.changes
+            .iter()
+            .filter_map(|change| change.tuple_key.as_ref()?.object.as_deref())
+            .collect()
+    }
+
+    fn continuation_tokens(calls: &Calls) -> Vec<String> {
+        calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, query)| {
+                query["continuation_token"]
+                    .as_str()
+                    .unwrap_or("")
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("urkel-{}-{}.json", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn persisted(path: &Path) -> HashMap<String, String> {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn persists_tokens_by_replacing_their_file() {
+        let path = state_path("tokens");
+        let tokens = Tokens::load(Some(path.clone())).await.unwrap();
+        assert_eq!(tokens.get(STORE_ID).await, None);
+
+        tokens.set(STORE_ID, "1").await.unwrap();
+        tokens.set("other", "2").await.unwrap();
+        tokens.set(STORE_ID, "3").await.unwrap();
+        assert_eq!(
+            persisted(&path),
+            HashMap::from([
+                (STORE_ID.to_owned(), "3".to_owned()),
+                ("other".to_owned(), "2".to_owned()),
+            ])
+        );
+        assert!(!path.with_extension("tmp").exists());
+
+        let reloaded = Tokens::load(Some(path.clone())).await.unwrap();
+        assert_eq!(reloaded.get(STORE_ID).await.as_deref(), Some("3"));
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(Tokens::load(Some(path)).await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn publishes_and_persists_the_changes_it_polls() {
+        let log = Arc::new(SyncMutex::new(vec![change("doc:0", 0), change("doc:1", 1)]));
+        let path = state_path("poller");
+        let config = OpenFgaConfig {
+            watcher: WatcherConfig {
+                page_size: Some(10),
+                state_path: Some(path.clone()),
+                ..WatcherConfig::default()
+            },
+            ..OpenFgaConfig::default()
+        };
+        let (client, _) = openfga_with(config, changes_log(log.clone())).await;
+        let (changes, mut subscriber) = broadcast::channel(16);
+        let poller = Poller {
+            client: client.clone(),
+            store_id: STORE_ID.to_owned(),
+            tokens: Arc::new(Tokens::load(Some(path.clone())).await.unwrap()),
+            changes,
+        };
+
+        assert_eq!(poller.starting_token().await.unwrap(), "2");
+        assert_eq!(persisted(&path)[STORE_ID], "2");
+
+        log.lock().unwrap().push(change("doc:2", 2));
+        assert_eq!(poller.poll("2").await.unwrap().as_deref(), Some("3"));
+        assert_eq!(
+            subscriber.try_recv().unwrap(),
+            published(change("doc:2", 2), "3")
+        );
+        assert!(subscriber.try_recv().is_err());
+        assert_eq!(persisted(&path)[STORE_ID], "3");
+        assert_eq!(poller.poll("3").await.unwrap(), None);
+
+        let restarted = Poller {
+            tokens: Arc::new(Tokens::load(Some(path)).await.unwrap()),
+            ..poller
+        };
+        assert_eq!(restarted.starting_token().await.unwrap(), "3");
+    }
+
+    #[rocket::async_test]
+    async fn catches_up_then_skips_the_published_changes_already_read() {
+        let log = Arc::new(SyncMutex::new(vec![
+            change("doc:0", 0),
+            change("folder:1", 1),
+            change("doc:2", 2),
+        ]));
+        let (client, calls) = openfga(changes_log(log)).await;
+        let watcher = watcher(16);
+        let mut pages = watcher
+            .changes(&client, STORE_ID, Some("doc"), Some(10), Some("0"))
+            .unwrap();
+
+        // Published by the poller while the follower was catching up.
+        watcher
+            .changes
+            .send(published(change("doc:2", 2), "3"))
+            .unwrap();
+        watcher
+            .changes
+            .send(published(change("doc:3", 3), "4"))
+            .unwrap();
+
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:0", "doc:2"]);
+        assert_eq!(page.continuation_token, "3");
+
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:3"]);
+        assert_eq!(page.continuation_token, "4");
+        assert_eq!(continuation_tokens(&calls), vec!["0", "3"]);
+    }
+
+    #[rocket::async_test]
+    async fn catches_up_again_after_lagging_behind() {
+        let log = Arc::new(SyncMutex::new(vec![change("doc:0", 0)]));
+        let (client, calls) = openfga(changes_log(log.clone())).await;
+        let watcher = watcher(2);
+        let mut pages = watcher
+            .changes(&client, STORE_ID, None, Some(10), None)
+            .unwrap();
+
+        watcher
+            .changes
+            .send(published(change("doc:0", 0), "1"))
+            .unwrap();
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:0"]);
+        assert_eq!(page.continuation_token, "1");
+
+        for index in 1..4 {
+            let change = change(&format!("doc:{}", index), index);
+            log.lock().unwrap().push(change.clone());
+            let continuation_token = (index + 1).to_string();
+            watcher
+                .changes
+                .send(published(change, &continuation_token))
+                .unwrap();
+        }
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:1", "doc:2", "doc:3"]);
+        assert_eq!(page.continuation_token, "4");
+
+        log.lock().unwrap().push(change("doc:4", 4));
+        watcher
+            .changes
+            .send(published(change("doc:4", 4), "5"))
+            .unwrap();
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:4"]);
+        assert_eq!(page.continuation_token, "5");
+        assert_eq!(continuation_tokens(&calls), vec!["1", "4"]);
+    }
+
+    #[rocket::async_test]
+    async fn reads_again_the_pages_missed_in_part() {
+        let log: Vec<_> = (0..5)
+            .map(|index| change(&format!("doc:{}", index), index))
+            .collect();
+        let (client, calls) = openfga(changes_log(Arc::new(SyncMutex::new(log.clone())))).await;
+        let watcher = watcher(2);
+        let mut pages = watcher
+            .changes(&client, STORE_ID, None, Some(10), None)
+            .unwrap();
+        let publish = |index: usize, continuation_token: &str| {
+            watcher
+                .changes
+                .send(published(log[index].clone(), continuation_token))
+                .unwrap();
+        };
+
+        publish(0, "1");
+        publish(1, "4");
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:0"]);
+
+        // The second change of the page is missed, after its first one was received.
+        publish(2, "4");
+        publish(3, "4");
+        publish(4, "5");
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(objects(&page), vec!["doc:1", "doc:2", "doc:3", "doc:4"]);
+        assert_eq!(page.continuation_token, "5");
+        assert_eq!(continuation_tokens(&calls), vec!["1"]);
+    }
+}
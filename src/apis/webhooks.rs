@@ -0,0 +1,781 @@
+use super::watcher::{timestamp, ChangeWatcher, StoreChange};
+use super::UrkelClient;
+use crate::config::{WebhookConfig, WebhooksConfig};
+use ring::hmac;
+use rocket::tokio::{
+    self,
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A change as posted to a webhook, signed in the `X-Urkel-Signature` header with
+/// `sha256=<hex HMAC-SHA256 of "<X-Urkel-Timestamp>.<body>">`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// Identifies the change across endpoints and retries.
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(flatten)]
+    pub change: StoreChange,
+}
+
+/// Deliveries of a webhook since Urkel started. Times are in milliseconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct WebhookStatus {
+    #[serde(rename = "url")]
+    pub url: String,
+    #[serde(rename = "delivered")]
+    pub delivered: u64,
+    /// Attempts repeated after a failure.
+    #[serde(rename = "retried")]
+    pub retried: u64,
+    /// Deliveries given up on, including those dropped because the queue was full.
+    #[serde(rename = "dead_lettered")]
+    pub dead_lettered: u64,
+    /// Deliveries queued or in progress.
+    #[serde(rename = "pending")]
+    pub pending: u64,
+    #[serde(rename = "last_delivered_at", skip_serializing_if = "Option::is_none")]
+    pub last_delivered_at: Option<u64>,
+    #[serde(rename = "last_failed_at", skip_serializing_if = "Option::is_none")]
+    pub last_failed_at: Option<u64>,
+    #[serde(rename = "last_error", skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Posts the changes published by a `ChangeWatcher` to the configured webhooks. Each endpoint
+/// receives its changes in order, through its own queue, so a slow endpoint does not hold the
+/// others back: the changes for an endpoint whose queue is full are dead-lettered. Changes the
+/// dispatcher misses by lagging behind the watcher are read back from OpenFGA. The deliveries
+/// stop when the dispatcher is dropped.
+#[derive(Debug)]
+pub struct WebhookDispatcher {
+    statuses: Vec<Arc<Mutex<WebhookStatus>>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl WebhookDispatcher {
+    /// Subscribes to `watcher` and starts the deliveries, reading the changes missed with
+    /// `client`. Must be called within a Tokio runtime.
+    pub fn spawn(
+        client: &UrkelClient,
+        watcher: &ChangeWatcher,
+        config: &WebhooksConfig,
+    ) -> Result<WebhookDispatcher, reqwest::Error> {
+        let http = reqwest::Client::builder()
+            .timeout(config.timeout())
+            .build()?;
+        let dead_letters = Arc::new(DeadLetters::new(config.dead_letter_path.clone()));
+
+        let mut statuses = Vec::new();
+        let mut queues = Vec::new();
+        let mut tasks = Vec::new();
+        for endpoint in &config.endpoints {
+            let status = Arc::new(Mutex::new(WebhookStatus {
+                url: endpoint.url.clone(),
+                ..WebhookStatus::default()
+            }));
+            let (queue, events) = mpsc::channel(config.queue_capacity);
+            let deliverer = Deliverer {
+                endpoint: endpoint.clone(),
+                key: hmac::Key::new(hmac::HMAC_SHA256, endpoint.secret.as_bytes()),
+                http: http.clone(),
+                status: status.clone(),
+                dead_letters: dead_letters.clone(),
+                max_attempts: config.max_attempts,
+                initial_backoff: config.initial_backoff(),
+                max_backoff: config.max_backoff(),
+            };
+            tasks.push(tokio::spawn(deliverer.run(events)));
+            queues.push((endpoint.clone(), status.clone(), queue));
+            statuses.push(status);
+        }
+        tasks.push(tokio::spawn(dispatch(
+            client.clone(),
+            watcher.stores().cloned().collect(),
+            watcher.subscribe(),
+            queues,
+            dead_letters,
+        )));
+
+        Ok(WebhookDispatcher { statuses, tasks })
+    }
+
+    pub fn statuses(&self) -> Vec<WebhookStatus> {
+        self.statuses
+            .iter()
+            .map(|status| lock(status).clone())
+            .collect()
+    }
+}
+
+impl Drop for WebhookDispatcher {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn lock(status: &Mutex<WebhookStatus>) -> std::sync::MutexGuard<'_, WebhookStatus> {
+    status.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+fn now_millis() -> u64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+}
+
+type Queue = (
+    WebhookConfig,
+    Arc<Mutex<WebhookStatus>>,
+    mpsc::Sender<WebhookEvent>,
+);
+
+/// Whether the changes of the tuple are wanted by `endpoint`.
+fn matches(endpoint: &WebhookConfig, change: &StoreChange) -> bool {
+    let tuple_key = change.change.tuple_key.as_ref();
+    let object = tuple_key
+        .and_then(|key| key.object.as_deref())
+        .unwrap_or("");
+    let relation = tuple_key
+        .and_then(|key| key.relation.as_deref())
+        .unwrap_or("");
+    let object_type = object
+        .split_once(':')
+        .map_or(object, |(object_type, _)| object_type);
+    (endpoint.types.is_empty() || endpoint.types.iter().any(|wanted| wanted == object_type))
+        && (endpoint.relations.is_empty()
+            || endpoint.relations.iter().any(|wanted| wanted == relation))
+}
+
+/// Where the changes of a store were dispatched up to.
+#[derive(Debug, Default)]
+struct Dispatched {
+    /// A token preceding every change of the store not dispatched yet, from which the changes
+    /// missed while lagging behind are read back.
+    resume_from: String,
+    /// The token following the page of the latest change dispatched.
+    page: Option<String>,
+    /// The time of the latest change dispatched. The changes until then are not dispatched again.
+    latest: Option<(i64, i32)>,
+}
+
+impl Dispatched {
+    /// Whether `change` is yet to be dispatched, in which case it is counted as dispatched.
+    fn first_time(&mut self, change: &StoreChange) -> bool {
+        let time = timestamp(&change.change);
+        if time.is_some() && time <= self.latest {
+            return false;
+        }
+        self.latest = time.max(self.latest);
+        // The changes of a page share its token, so the previous page was fully dispatched.
+        if self.page.as_ref() != Some(&change.continuation_token) {
+            if let Some(page) = self.page.replace(change.continuation_token.clone()) {
+                self.resume_from = page;
+            }
+        }
+        true
+    }
+}
+
+/// Queues the changes of `store_ids` for the endpoints wanting them. The changes from the latest
+/// one of each store on are read from OpenFGA before `changes` are received, and read again
+/// after lagging behind them, skipping those already dispatched.
+async fn dispatch(
+    client: UrkelClient,
+    store_ids: Vec<String>,
+    changes: broadcast::Receiver<StoreChange>,
+    queues: Vec<Queue>,
+    dead_letters: Arc<DeadLetters>,
+) {
+    let poll_interval = client.config.watcher.poll_interval();
+    let page_size = client.config.watcher.page_size;
+    let mut stores = HashMap::new();
+    for store_id in store_ids {
+        let resume_from = loop {
+            match client
+                .latest_changes_token(&store_id, None, page_size)
+                .await
+            {
+                Ok(continuation_token) => break continuation_token,
+                Err(error) => {
+                    eprintln!(
+                        "Could not start the webhooks of store {}: {}",
+                        store_id, error
+                    );
+                    tokio::time::sleep(poll_interval).await;
+                }
+            }
+        };
+        let dispatched = Dispatched {
+            resume_from,
+            ..Dispatched::default()
+        };
+        stores.insert(store_id, dispatched);
+    }
+    // Changes published while the tokens were read are caught up with instead.
+    let mut changes = changes.resubscribe();
+
+    let mut behind = true;
+    loop {
+        if behind {
+            for (store_id, dispatched) in &mut stores {
+                catch_up(&client, store_id, dispatched, &queues, &dead_letters).await;
+            }
+            behind = false;
+        }
+        let change = match changes.recv().await {
+            Ok(change) => change,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                eprintln!("Webhooks missed {} changes, reading them back.", missed);
+                behind = true;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let first_time = stores
+            .get_mut(&change.store_id)
+            .is_none_or(|dispatched| dispatched.first_time(&change));
+        if first_time {
+            enqueue(change, &queues, &dead_letters).await;
+        }
+    }
+}
+
+/// Queues the changes of a store from `dispatched.resume_from` on, until its latest one. Failed
+/// reads are retried after the poll interval.
+async fn catch_up(
+    client: &UrkelClient,
+    store_id: &str,
+    dispatched: &mut Dispatched,
+    queues: &[Queue],
+    dead_letters: &DeadLetters,
+) {
+    let page_size = client.config.watcher.page_size;
+    let mut continuation_token = dispatched.resume_from.clone();
+    loop {
+        let page = match client
+            .read_changes(store_id, None, page_size, Some(&continuation_token))
+            .await
+        {
+            Ok(page) => page.into_inner(),
+            Err(error) => {
+                eprintln!(
+                    "Could not read the changes of store {} for the webhooks: {}",
+                    store_id, error
+                );
+                tokio::time::sleep(client.config.watcher.poll_interval()).await;
+                continue;
+            }
+        };
+        if page.changes.is_empty() {
+            return;
+        }
+        for change in page.changes {
+            let change = StoreChange {
+                store_id: store_id.to_owned(),
+                change,
+                continuation_token: page.continuation_token.clone(),
+            };
+            if dispatched.first_time(&change) {
+                enqueue(change, queues, dead_letters).await;
+            }
+        }
+        continuation_token = page.continuation_token;
+    }
+}
+
+/// Queues a change for the endpoints wanting it, without waiting for a full queue.
+async fn enqueue(change: StoreChange, queues: &[Queue], dead_letters: &DeadLetters) {
+    let event = WebhookEvent {
+        id: format!("{:032x}", rand::random::<u128>()),
+        change,
+    };
+    for (endpoint, status, queue) in queues {
+        if !matches(endpoint, &event.change) {
+            continue;
+        }
+        lock(status).pending += 1;
+        match queue.try_send(event.clone()) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(event)) => {
+                {
+                    let mut status = lock(status);
+                    status.pending -= 1;
+                    status.dead_lettered += 1;
+                }
+                let error = format!("The queue of {} is full.", endpoint.url);
+                dead_letters.add(&endpoint.url, &event, 0, &error).await;
+            }
+            // The deliverer is only gone along with the dispatcher.
+            Err(mpsc::error::TrySendError::Closed(_)) => lock(status).pending -= 1,
+        }
+    }
+}
+
+/// Why an attempt failed, and whether repeating it may help.
+struct Failure {
+    message: String,
+    retryable: bool,
+}
+
+/// The deliveries to a single endpoint.
+struct Deliverer {
+    endpoint: WebhookConfig,
+    key: hmac::Key,
+    http: reqwest::Client,
+    status: Arc<Mutex<WebhookStatus>>,
+    dead_letters: Arc<DeadLetters>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Deliverer {
+    async fn run(self, mut events: mpsc::Receiver<WebhookEvent>) {
+        while let Some(event) = events.recv().await {
+            self.deliver(&event).await;
+            lock(&self.status).pending -= 1;
+        }
+    }
+
+    async fn deliver(&self, event: &WebhookEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(error) => {
+                eprintln!("Could not serialize webhook event {}: {}", event.id, error);
+                return;
+            }
+        };
+
+        let mut backoff = self.initial_backoff;
+        let mut attempts = 0;
+        let failure = loop {
+            attempts += 1;
+            let failure = match self.post(&body).await {
+                Ok(()) => {
+                    let mut status = lock(&self.status);
+                    status.delivered += 1;
+                    status.last_delivered_at = Some(now_millis());
+                    return;
+                }
+                Err(failure) => failure,
+            };
+            {
+                let mut status = lock(&self.status);
+                status.last_failed_at = Some(now_millis());
+                status.last_error = Some(failure.message.clone());
+            }
+            if !failure.retryable || attempts >= self.max_attempts {
+                break failure;
+            }
+            lock(&self.status).retried += 1;
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        };
+
+        lock(&self.status).dead_lettered += 1;
+        self.dead_letters
+            .add(&self.endpoint.url, event, attempts, &failure.message)
+            .await;
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<(), Failure> {
+        let timestamp = (now_millis() / 1_000).to_string();
+        let mut signed = format!("{}.", timestamp).into_bytes();
+        signed.extend_from_slice(body);
+        let signature = hmac::sign(&self.key, &signed)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let response = self
+            .http
+            .post(&self.endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Urkel-Timestamp", timestamp)
+            .header("X-Urkel-Signature", format!("sha256={}", signature))
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|error| Failure {
+                message: error.to_string(),
+                retryable: true,
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        Err(Failure {
+            message: format!("{} answered {}", self.endpoint.url, status),
+            retryable: status.is_server_error()
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        })
+    }
+}
+
+/// A delivery given up on, as written to the dead-letter file.
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    #[serde(rename = "url")]
+    url: &'a str,
+    #[serde(rename = "event")]
+    event: &'a WebhookEvent,
+    #[serde(rename = "attempts")]
+    attempts: u32,
+    #[serde(rename = "error")]
+    error: &'a str,
+    #[serde(rename = "failed_at")]
+    failed_at: u64,
+}
+
+/// The dead-letter file, appended one line at a time.
+#[derive(Debug)]
+struct DeadLetters {
+    path: Option<PathBuf>,
+    file: tokio::sync::Mutex<()>,
+}
+
+impl DeadLetters {
+    fn new(path: Option<PathBuf>) -> DeadLetters {
+        DeadLetters {
+            path,
+            file: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Appends a delivery given up on after `attempts`, logging it when it cannot be written.
+    async fn add(&self, url: &str, event: &WebhookEvent, attempts: u32, error: &str) {
+        let dead_letter = DeadLetter {
+            url,
+            event,
+            attempts,
+            error,
+            failed_at: now_millis(),
+        };
+        if let Err(error) = self.append(&dead_letter).await {
+            eprintln!(
+                "Could not dead-letter webhook event {} for {}: {}",
+                event.id, url, error
+            );
+        }
+    }
+
+    /// Logs the delivery when no file is configured.
+    async fn append(&self, dead_letter: &DeadLetter<'_>) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(dead_letter)?;
+        let path = match &self.path {
+            Some(path) => path,
+            None => {
+                eprintln!("Dead webhook delivery: {}", String::from_utf8_lossy(&line));
+                return Ok(());
+            }
+        };
+        line.push(b'\n');
+        let _file = self.file.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::openfga::{TupleChange, TupleKey};
+    use crate::apis::tests::{changes_log, openfga, STORE_ID};
+    use rocket::tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+    use std::path::Path;
+
+    /// An endpoint answering its requests with `statuses` in turn. Returns its URL and the
+    /// requests it received.
+    async fn webhook_endpoint(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                received.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} Hook\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = header(head, "content-length")
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length || read == 0 {
+                    return text;
+                }
+            }
+        }
+    }
+
+    fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+        head.lines().find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            header.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    fn dead_letter_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("urkel-{}-{}.ndjson", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn dead_letters(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn endpoint(url: &str) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_owned(),
+            secret: "secret".to_owned(),
+            types: Vec::new(),
+            relations: Vec::new(),
+        }
+    }
+
+    fn deliverer(url: &str, dead_letter_path: &Path) -> Deliverer {
+        Deliverer {
+            endpoint: endpoint(url),
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"secret"),
+            http: reqwest::Client::new(),
+            status: Arc::new(Mutex::new(WebhookStatus::default())),
+            dead_letters: Arc::new(DeadLetters::new(Some(dead_letter_path.to_path_buf()))),
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn change(object: &str) -> StoreChange {
+        StoreChange {
+            store_id: "store".to_owned(),
+            change: TupleChange {
+                tuple_key: Some(TupleKey {
+                    object: Some(object.to_owned()),
+                    relation: Some("viewer".to_owned()),
+                    user: Some("user:a".to_owned()),
+                }),
+                ..TupleChange::default()
+            },
+            continuation_token: "token".to_owned(),
+        }
+    }
+
+    fn event(object: &str) -> WebhookEvent {
+        WebhookEvent {
+            id: object.to_owned(),
+            change: change(object),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn signs_the_deliveries() {
+        let (url, requests) = webhook_endpoint(vec![200]).await;
+        let path = dead_letter_path("signed");
+        let deliverer = deliverer(&url, &path);
+
+        deliverer.deliver(&event("doc:1")).await;
+
+        let request = requests.lock().unwrap()[0].clone();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let timestamp = header(head, "x-urkel-timestamp").unwrap();
+        let signature = header(head, "x-urkel-signature")
+            .and_then(|signature| signature.strip_prefix("sha256="))
+            .unwrap();
+        let signature = (0..signature.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&signature[index..index + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let signed = format!("{}.{}", timestamp, body);
+        assert!(hmac::verify(&key, signed.as_bytes(), &signature).is_ok());
+        assert_eq!(
+            serde_json::from_str::<WebhookEvent>(body).unwrap(),
+            event("doc:1")
+        );
+        let status = lock(&deliverer.status).clone();
+        assert_eq!((status.delivered, status.retried), (1, 0));
+        assert!(dead_letters(&path).is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn retries_server_errors_then_dead_letters() {
+        let (url, requests) = webhook_endpoint(vec![503, 500, 502]).await;
+        let path = dead_letter_path("retried");
+        let deliverer = deliverer(&url, &path);
+
+        deliverer.deliver(&event("doc:1")).await;
+
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        let status = lock(&deliverer.status).clone();
+        assert_eq!(
+            (status.delivered, status.retried, status.dead_lettered),
+            (0, 2, 1)
+        );
+        let dead_letters = dead_letters(&path);
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0]["attempts"], 3);
+        assert_eq!(dead_letters[0]["event"]["id"], "doc:1");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_retry_client_errors() {
+        let (url, requests) = webhook_endpoint(vec![400]).await;
+        let path = dead_letter_path("rejected");
+        let deliverer = deliverer(&url, &path);
+
+        deliverer.deliver(&event("doc:1")).await;
+
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        let status = lock(&deliverer.status).clone();
+        assert_eq!((status.retried, status.dead_lettered), (0, 1));
+        assert_eq!(dead_letters(&path)[0]["attempts"], 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[rocket::async_test]
+    async fn dead_letters_changes_for_full_queues() {
+        let path = dead_letter_path("full");
+        let (client, calls) = openfga(changes_log(Arc::default())).await;
+        let (changes, subscription) = broadcast::channel(8);
+        let (queue, mut events) = mpsc::channel(1);
+        let status = Arc::new(Mutex::new(WebhookStatus::default()));
+        let queues = vec![(endpoint("http://127.0.0.1:9/hooks"), status.clone(), queue)];
+        let dispatcher = tokio::spawn(dispatch(
+            client,
+            vec![STORE_ID.to_owned()],
+            subscription,
+            queues,
+            Arc::new(DeadLetters::new(Some(path.clone()))),
+        ));
+        // The latest token is read, then the changes following it.
+        while calls.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for object in ["doc:1", "doc:2", "doc:3"] {
+            changes.send(change(object)).unwrap();
+        }
+        drop(changes);
+        dispatcher.await.unwrap();
+
+        let status = lock(&status).clone();
+        assert_eq!((status.pending, status.dead_lettered), (1, 2));
+        assert_eq!(events.recv().await.unwrap().change, change("doc:1"));
+        let dead_letters = dead_letters(&path);
+        let objects: Vec<_> = dead_letters
+            .iter()
+            .map(|dead_letter| {
+                assert_eq!(dead_letter["attempts"], 0);
+                dead_letter["event"]["change"]["tuple_key"]["object"].clone()
+            })
+            .collect();
+        assert_eq!(objects, vec!["doc:2", "doc:3"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[rocket::async_test]
+    async fn reads_back_the_changes_missed_while_lagging_behind() {
+        let timed_change = |index: i64| {
+            let mut change = change(&format!("doc:{}", index)).change;
+            change.timestamp = Some(prost_wkt_types::Timestamp {
+                seconds: index + 1,
+                nanos: 0,
+            });
+            change
+        };
+        let log = Arc::new(Mutex::new(vec![timed_change(0)]));
+        let (client, calls) = openfga(changes_log(log.clone())).await;
+        let (changes, subscription) = broadcast::channel(2);
+        let (queue, mut events) = mpsc::channel(16);
+        let status = Arc::new(Mutex::new(WebhookStatus::default()));
+        let queues = vec![(endpoint("http://127.0.0.1:9/hooks"), status, queue)];
+        let _dispatcher = tokio::spawn(dispatch(
+            client,
+            vec![STORE_ID.to_owned()],
+            subscription,
+            queues,
+            Arc::new(DeadLetters::new(None)),
+        ));
+        // The latest token, "1", is read, then the changes following it.
+        while calls.lock().unwrap().len() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let publish = |index: i64| {
+            let change = timed_change(index);
+            log.lock().unwrap().push(change.clone());
+            let continuation_token = (index + 1).to_string();
+            changes
+                .send(StoreChange {
+                    store_id: STORE_ID.to_owned(),
+                    change,
+                    continuation_token,
+                })
+                .unwrap();
+        };
+        for index in 1..5 {
+            publish(index);
+        }
+        let mut objects = Vec::new();
+        for _ in 0..4 {
+            let event = events.recv().await.unwrap();
+            objects.push(event.change.change.tuple_key.unwrap().object.unwrap());
+        }
+        assert_eq!(objects, vec!["doc:1", "doc:2", "doc:3", "doc:4"]);
+        let read_back = calls.lock().unwrap()[3].1["continuation_token"].clone();
+        assert_eq!(read_back, "1");
+
+        publish(5);
+        let event = events.recv().await.unwrap();
+        assert_eq!(
+            event.change.change.tuple_key.unwrap().object.unwrap(),
+            "doc:5"
+        );
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,1005 @@
+use crate::models::InternalErrorCode;
+use rocket::figment::{
+    providers::{Format, Serialized, Toml},
+    value::Value,
+    Figment,
+};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Path of the configuration file read when `URKEL_CONFIG` is not set.
+pub const DEFAULT_CONFIG_PATH: &str = "Urkel.toml";
+
+/// Configuration for the OpenFGA client and the HTTP wrapping server.
+///
+/// Values are read, in increasing order of precedence, from the defaults below, the TOML file
+/// at `URKEL_CONFIG` (or `Urkel.toml`), the legacy `OPENFGA_ADDR`, `OPENFGA_BEARER_TOKEN` and
+/// `URKEL_BEARER_TOKEN` variables, and `URKEL_`-prefixed variables where `__` separates nested
+/// keys, e.g. `URKEL_OPENFGA__API_URL`. Variables are read as strings, so that a token like `0123`
+/// keeps its leading zero, and converted when a number or a boolean is expected. Arrays and tables
+/// are written as in TOML, e.g. `URKEL_OPENFGA__WATCHER__STORES=["..."]`.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrkelConfig {
+    #[serde(rename = "openfga")]
+    pub openfga: OpenFgaConfig,
+    #[serde(rename = "server")]
+    pub server: ServerConfig,
+    #[serde(rename = "webhooks")]
+    pub webhooks: WebhooksConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenFgaConfig {
+    #[serde(rename = "api_url")]
+    pub api_url: String,
+    /// Protocol spoken with OpenFGA at `api_url`.
+    #[serde(rename = "transport")]
+    pub transport: Transport,
+    #[serde(rename = "credentials")]
+    pub credentials: CredentialsConfig,
+    #[serde(rename = "timeouts")]
+    pub timeouts: TimeoutConfig,
+    #[serde(rename = "retry")]
+    pub retry: RetryConfig,
+    #[serde(rename = "read_limits")]
+    pub read_limits: ReadLimitsConfig,
+    #[serde(rename = "cache")]
+    pub cache: CacheConfig,
+    #[serde(rename = "watcher")]
+    pub watcher: WatcherConfig,
+    /// TLS settings for the connection to OpenFGA. `https` URLs use the system roots when unset.
+    #[serde(rename = "tls", skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Maximum number of checks sent to OpenFGA at once by the batched operations.
+    #[serde(rename = "max_concurrent_checks")]
+    pub max_concurrent_checks: usize,
+    /// Store used by library callers that do not track a store of their own.
+    #[serde(rename = "store_id", skip_serializing_if = "Option::is_none")]
+    pub store_id: Option<String>,
+    /// Authorization model used when a request does not name one.
+    #[serde(
+        rename = "authorization_model_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub authorization_model_id: Option<String>,
+}
+
+impl Default for OpenFgaConfig {
+    fn default() -> OpenFgaConfig {
+        OpenFgaConfig {
+            api_url: "grpc://[::1]:8081".to_owned(),
+            transport: Transport::default(),
+            credentials: CredentialsConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            retry: RetryConfig::default(),
+            read_limits: ReadLimitsConfig::default(),
+            cache: CacheConfig::default(),
+            watcher: WatcherConfig::default(),
+            tls: None,
+            max_concurrent_checks: 2,
+            store_id: None,
+            authorization_model_id: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Transport {
+    #[default]
+    #[serde(rename = "grpc")]
+    Grpc,
+    /// OpenFGA's HTTP API, for deployments which only expose that port.
+    #[serde(rename = "http")]
+    Http,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum CredentialsMethod {
+    #[serde(rename = "none")]
+    None,
+    #[default]
+    #[serde(rename = "api_token")]
+    ApiToken,
+    #[serde(rename = "client_credentials")]
+    ClientCredentials,
+}
+
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CredentialsConfig {
+    #[serde(rename = "method")]
+    pub method: CredentialsMethod,
+    /// Preshared token sent as a bearer token when `method` is `api_token`.
+    #[serde(rename = "api_token", skip_serializing_if = "Option::is_none")]
+    pub api_token: Option<String>,
+    /// OAuth2 token endpoint used when `method` is `client_credentials`.
+    #[serde(rename = "token_url", skip_serializing_if = "Option::is_none")]
+    pub token_url: Option<String>,
+    #[serde(rename = "client_id", skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(rename = "client_secret", skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(rename = "audience", skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    #[serde(rename = "scopes", skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+impl fmt::Debug for CredentialsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CredentialsConfig")
+            .field("method", &self.method)
+            .field("api_token", &self.api_token.as_ref().map(|_| "<redacted>"))
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| "<redacted>"),
+            )
+            .field("audience", &self.audience)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+/// Kinds of OpenFGA calls, each of which can be given its own deadline.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Operation {
+    /// Reads of stores, models, tuples, changes and assertions.
+    Read,
+    /// Changes to stores, models, tuples and assertions.
+    Write,
+    Check,
+    Expand,
+    ListObjects,
+}
+
+/// Deadlines of calls to OpenFGA, retries included. Unset per-operation values fall back to
+/// `request_ms`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// How long to wait for the connection to OpenFGA to be established.
+    #[serde(rename = "connect_ms")]
+    pub connect_ms: u64,
+    /// Deadline of any single call to OpenFGA.
+    #[serde(rename = "request_ms")]
+    pub request_ms: u64,
+    #[serde(rename = "read_ms", skip_serializing_if = "Option::is_none")]
+    pub read_ms: Option<u64>,
+    #[serde(rename = "write_ms", skip_serializing_if = "Option::is_none")]
+    pub write_ms: Option<u64>,
+    #[serde(rename = "check_ms", skip_serializing_if = "Option::is_none")]
+    pub check_ms: Option<u64>,
+    #[serde(rename = "expand_ms", skip_serializing_if = "Option::is_none")]
+    pub expand_ms: Option<u64>,
+    #[serde(rename = "list_objects_ms", skip_serializing_if = "Option::is_none")]
+    pub list_objects_ms: Option<u64>,
+    /// Budget of operations made of several calls, like `check_n_of_m` and `check_horizontal`.
+    #[serde(rename = "composite_ms")]
+    pub composite_ms: u64,
+}
+
+impl TimeoutConfig {
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(self.connect_ms)
+    }
+
+    pub fn request(&self) -> Duration {
+        Duration::from_millis(self.request_ms)
+    }
+
+    /// The deadline of a single call of the given kind.
+    pub fn operation(&self, operation: Operation) -> Duration {
+        let operation_ms = match operation {
+            Operation::Read => self.read_ms,
+            Operation::Write => self.write_ms,
+            Operation::Check => self.check_ms,
+            Operation::Expand => self.expand_ms,
+            Operation::ListObjects => self.list_objects_ms,
+        };
+        Duration::from_millis(operation_ms.unwrap_or(self.request_ms))
+    }
+
+    pub fn composite(&self) -> Duration {
+        Duration::from_millis(self.composite_ms)
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> TimeoutConfig {
+        TimeoutConfig {
+            connect_ms: 5_000,
+            request_ms: 30_000,
+            read_ms: None,
+            write_ms: None,
+            check_ms: None,
+            expand_ms: None,
+            list_objects_ms: None,
+            composite_ms: 60_000,
+        }
+    }
+}
+
+/// How calls failing with a transient error are repeated. Writes that OpenFGA may already have
+/// applied are never repeated, whatever the code.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Attempts per call, including the first one. `1` disables retries.
+    #[serde(rename = "max_attempts")]
+    pub max_attempts: u32,
+    #[serde(rename = "initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(rename = "max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Factor applied to the backoff after each attempt.
+    #[serde(rename = "multiplier")]
+    pub multiplier: f64,
+    /// Fraction of each backoff, between 0 and 1, which is randomly shaved off.
+    #[serde(rename = "jitter")]
+    pub jitter: f64,
+    #[serde(rename = "retryable_codes")]
+    pub retryable_codes: Vec<InternalErrorCode>,
+}
+
+impl RetryConfig {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 2_000,
+            multiplier: 2.0,
+            jitter: 0.5,
+            retryable_codes: vec![
+                InternalErrorCode::Unavailable,
+                InternalErrorCode::ResourceExhausted,
+                InternalErrorCode::Aborted,
+            ],
+        }
+    }
+}
+
+/// Upper bounds of reads following continuation tokens, like `read_until_end`. Callers may ask
+/// for lower ones.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReadLimitsConfig {
+    #[serde(rename = "max_tuples")]
+    pub max_tuples: usize,
+    #[serde(rename = "max_pages", skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<usize>,
+}
+
+impl Default for ReadLimitsConfig {
+    fn default() -> ReadLimitsConfig {
+        ReadLimitsConfig {
+            max_tuples: 10_000,
+            max_pages: None,
+        }
+    }
+}
+
+/// In-process cache of check decisions. Cached decisions may miss tuple changes made by other
+/// clients for up to `ttl_ms`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    #[serde(rename = "enabled")]
+    pub enabled: bool,
+    #[serde(rename = "ttl_ms")]
+    pub ttl_ms: u64,
+    #[serde(rename = "max_entries")]
+    pub max_entries: usize,
+}
+
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_millis(self.ttl_ms)
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            enabled: false,
+            ttl_ms: 10_000,
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// Background polling of the changes of some stores, which invalidates their cached decisions and
+/// is published to in-process subscribers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatcherConfig {
+    #[serde(rename = "enabled")]
+    pub enabled: bool,
+    /// Stores to watch, `openfga.store_id` when empty.
+    #[serde(rename = "stores", skip_serializing_if = "Vec::is_empty")]
+    pub stores: Vec<String>,
+    /// Pause between polls once a store has no new changes.
+    #[serde(rename = "poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(rename = "page_size", skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<i32>,
+    /// JSON file keeping the continuation token of each store across restarts. Stores without a
+    /// token start from their latest change.
+    #[serde(rename = "state_path", skip_serializing_if = "Option::is_none")]
+    pub state_path: Option<PathBuf>,
+    /// Changes buffered for each subscriber, which misses the older ones when it lags behind.
+    #[serde(rename = "channel_capacity")]
+    pub channel_capacity: usize,
+}
+
+impl WatcherConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+impl Default for WatcherConfig {
+    fn default() -> WatcherConfig {
+        WatcherConfig {
+            enabled: false,
+            stores: Vec::new(),
+            poll_interval_ms: 1_000,
+            page_size: None,
+            state_path: None,
+            channel_capacity: 1_024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM bundle of certificate authorities trusted in addition to the system roots.
+    #[serde(rename = "ca_cert_path", skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<PathBuf>,
+    /// PEM certificate presented to OpenFGA for mutual TLS, along with `client_key_path`.
+    #[serde(rename = "client_cert_path", skip_serializing_if = "Option::is_none")]
+    pub client_cert_path: Option<PathBuf>,
+    #[serde(rename = "client_key_path", skip_serializing_if = "Option::is_none")]
+    pub client_key_path: Option<PathBuf>,
+    /// Name checked against OpenFGA's certificate instead of the host of `api_url`.
+    #[serde(rename = "domain_name", skip_serializing_if = "Option::is_none")]
+    pub domain_name: Option<String>,
+}
+
+/// Options of the HTTP server. Unset address and port fall back to Rocket's own configuration.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    #[serde(rename = "address", skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(rename = "port", skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Key callers must send in the `X-URKEL-KEY` header.
+    #[serde(rename = "api_key", skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Value of the `Access-Control-Allow-Origin` response header.
+    #[serde(rename = "allowed_origin")]
+    pub allowed_origin: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: None,
+            port: None,
+            api_key: None,
+            allowed_origin: "*".to_owned(),
+        }
+    }
+}
+
+impl fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerConfig")
+            .field("address", &self.address)
+            .field("port", &self.port)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("allowed_origin", &self.allowed_origin)
+            .finish()
+    }
+}
+
+/// Webhooks receiving the changes seen by `openfga.watcher`, which must be enabled. Failed
+/// deliveries are retried with exponential backoff, then written to `dead_letter_path`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    #[serde(rename = "endpoints", skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<WebhookConfig>,
+    /// Attempts per delivery, including the first one.
+    #[serde(rename = "max_attempts")]
+    pub max_attempts: u32,
+    #[serde(rename = "initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(rename = "max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Deadline of each attempt.
+    #[serde(rename = "timeout_ms")]
+    pub timeout_ms: u64,
+    /// Deliveries waiting for each endpoint, beyond which its changes are dead-lettered.
+    #[serde(rename = "queue_capacity")]
+    pub queue_capacity: usize,
+    /// NDJSON file the deliveries which could not be made are appended to.
+    #[serde(rename = "dead_letter_path", skip_serializing_if = "Option::is_none")]
+    pub dead_letter_path: Option<PathBuf>,
+}
+
+impl WebhooksConfig {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> WebhooksConfig {
+        WebhooksConfig {
+            endpoints: Vec::new(),
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            timeout_ms: 10_000,
+            queue_capacity: 1_024,
+            dead_letter_path: None,
+        }
+    }
+}
+
+/// A URL changes are posted to, signed with HMAC-SHA256 and `secret`.
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    #[serde(rename = "url")]
+    pub url: String,
+    #[serde(rename = "secret")]
+    pub secret: String,
+    /// Object types whose changes are posted, all of them when empty.
+    #[serde(rename = "types", skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<String>,
+    /// Relations whose changes are posted, all of them when empty.
+    #[serde(rename = "relations", skip_serializing_if = "Vec::is_empty")]
+    pub relations: Vec<String>,
+}
+
+impl fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("url", &self.url)
+            .field("secret", &"<redacted>")
+            .field("types", &self.types)
+            .field("relations", &self.relations)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration sources could not be read or did not match the expected shape.
+    Source(Box<rocket::figment::Error>),
+    /// The configuration was read but holds an unusable value.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Source(error) => write!(f, "could not read configuration: {}", error),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<rocket::figment::Error> for ConfigError {
+    fn from(error: rocket::figment::Error) -> ConfigError {
+        ConfigError::Source(Box::new(error))
+    }
+}
+
+impl UrkelConfig {
+    /// The layered configuration sources, before extraction.
+    pub fn figment() -> Figment {
+        let path = env::var("URKEL_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_owned());
+        let vars = env::vars_os().map(|(var, value)| {
+            (
+                var.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            )
+        });
+        UrkelConfig::layered(&path, vars.collect())
+    }
+
+    /// The defaults, the file at `path` and the variables among `vars` which configure Urkel.
+    fn layered(path: &str, vars: Vec<(String, String)>) -> Figment {
+        let mut figment =
+            Figment::from(Serialized::defaults(UrkelConfig::default())).merge(Toml::file(path));
+
+        let legacy_vars = [
+            ("OPENFGA_ADDR", "openfga.api_url"),
+            ("OPENFGA_BEARER_TOKEN", "openfga.credentials.api_token"),
+            ("URKEL_BEARER_TOKEN", "server.api_key"),
+        ];
+        for (var, key) in legacy_vars {
+            if let Some((_, value)) = vars.iter().find(|(name, _)| name == var) {
+                figment = figment.merge(Serialized::default(key, value));
+            }
+        }
+
+        for (var, value) in &vars {
+            let key = match var.get(..6) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("URKEL_") => {
+                    var[6..].to_ascii_lowercase().replace("__", ".")
+                }
+                _ => continue,
+            };
+            if key.is_empty() || key == "config" || key == "bearer_token" {
+                continue;
+            }
+            figment = match value.parse::<Value>() {
+                Ok(parsed @ (Value::Array(..) | Value::Dict(..))) => {
+                    figment.merge(Serialized::default(&key, parsed))
+                }
+                _ => figment.merge(Serialized::default(&key, value)),
+            };
+        }
+        figment
+    }
+
+    /// Reads and validates the configuration.
+    pub fn load() -> Result<UrkelConfig, ConfigError> {
+        UrkelConfig::from_figment(&UrkelConfig::figment())
+    }
+
+    /// Extracts and validates the configuration of `figment`, reading numbers and booleans from
+    /// the strings of the environment.
+    pub fn from_figment(figment: &Figment) -> Result<UrkelConfig, ConfigError> {
+        let config: UrkelConfig = figment.extract_lossy()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.openfga.validate()?;
+        self.server.validate()?;
+        if !self.webhooks.endpoints.is_empty() && !self.openfga.watcher.enabled {
+            return Err(ConfigError::Invalid(
+                "`webhooks.endpoints` require `openfga.watcher.enabled`.".into(),
+            ));
+        }
+        self.webhooks.validate()
+    }
+}
+
+impl OpenFgaConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let api_url = url::Url::parse(&self.api_url).map_err(|error| {
+            ConfigError::Invalid(format!(
+                "`openfga.api_url` {:?} is not a valid URL: {}",
+                self.api_url, error
+            ))
+        })?;
+        if self.transport == Transport::Http && !["http", "https"].contains(&api_url.scheme()) {
+            return Err(ConfigError::Invalid(
+                "`openfga.api_url` must be an `http` or `https` URL when `openfga.transport` is \
+                 `http`."
+                    .into(),
+            ));
+        }
+        if let Some(tls) = &self.tls {
+            if api_url.scheme() != "https" {
+                return Err(ConfigError::Invalid(
+                    "`openfga.api_url` must use the `https` scheme when `openfga.tls` is set."
+                        .into(),
+                ));
+            }
+            if self.transport == Transport::Http && tls.domain_name.is_some() {
+                return Err(ConfigError::Invalid(
+                    "`openfga.tls.domain_name` is only supported by the `grpc` transport.".into(),
+                ));
+            }
+            tls.validate()?;
+        }
+        if self.max_concurrent_checks == 0 {
+            return Err(ConfigError::Invalid(
+                "`openfga.max_concurrent_checks` must be at least 1.".into(),
+            ));
+        }
+        let timeouts = [
+            Some(self.timeouts.connect_ms),
+            Some(self.timeouts.request_ms),
+            self.timeouts.read_ms,
+            self.timeouts.write_ms,
+            self.timeouts.check_ms,
+            self.timeouts.expand_ms,
+            self.timeouts.list_objects_ms,
+            Some(self.timeouts.composite_ms),
+        ];
+        if timeouts.contains(&Some(0)) {
+            return Err(ConfigError::Invalid(
+                "`openfga.timeouts` values must be greater than zero.".into(),
+            ));
+        }
+        self.retry.validate()?;
+        if self.read_limits.max_tuples == 0 || self.read_limits.max_pages == Some(0) {
+            return Err(ConfigError::Invalid(
+                "`openfga.read_limits` values must be greater than zero.".into(),
+            ));
+        }
+        if self.cache.enabled && (self.cache.ttl_ms == 0 || self.cache.max_entries == 0) {
+            return Err(ConfigError::Invalid(
+                "`openfga.cache` values must be greater than zero.".into(),
+            ));
+        }
+        if self.watcher.enabled {
+            if self.watcher.poll_interval_ms == 0 || self.watcher.channel_capacity == 0 {
+                return Err(ConfigError::Invalid(
+                    "`openfga.watcher` values must be greater than zero.".into(),
+                ));
+            }
+            if self.watcher.stores.is_empty() && self.store_id.is_none() {
+                return Err(ConfigError::Invalid(
+                    "`openfga.watcher.stores` must name a store when `openfga.store_id` is unset."
+                        .into(),
+                ));
+            }
+        }
+        if let Some(store_id) = &self.store_id {
+            validate_ulid("openfga.store_id", store_id)?;
+        }
+        for store_id in &self.watcher.stores {
+            validate_ulid("openfga.watcher.stores", store_id)?;
+        }
+        if let Some(authorization_model_id) = &self.authorization_model_id {
+            validate_ulid("openfga.authorization_model_id", authorization_model_id)?;
+        }
+        self.credentials.validate()
+    }
+}
+
+impl CredentialsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.method {
+            CredentialsMethod::None => Ok(()),
+            CredentialsMethod::ApiToken => match &self.api_token {
+                Some(token) if !token.is_empty() => Ok(()),
+                _ => Err(ConfigError::Invalid(
+                    "`openfga.credentials.api_token` (or `OPENFGA_BEARER_TOKEN`) is required \
+                     when the credentials method is `api_token`."
+                        .into(),
+                )),
+            },
+            CredentialsMethod::ClientCredentials => {
+                let required = [
+                    ("openfga.credentials.token_url", &self.token_url),
+                    ("openfga.credentials.client_id", &self.client_id),
+                    ("openfga.credentials.client_secret", &self.client_secret),
+                ];
+                for (key, value) in required {
+                    if value.as_deref().unwrap_or_default().is_empty() {
+                        return Err(ConfigError::Invalid(format!(
+                            "`{}` is required when the credentials method is \
+                             `client_credentials`.",
+                            key
+                        )));
+                    }
+                }
+                let token_url = self.token_url.as_deref().unwrap_or_default();
+                url::Url::parse(token_url).map_err(|error| {
+                    ConfigError::Invalid(format!(
+                        "`openfga.credentials.token_url` {:?} is not a valid URL: {}",
+                        token_url, error
+                    ))
+                })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_attempts == 0 {
+            return Err(ConfigError::Invalid(
+                "`openfga.retry.max_attempts` must be at least 1.".into(),
+            ));
+        }
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err(ConfigError::Invalid(
+                "`openfga.retry.initial_backoff_ms` must not exceed `max_backoff_ms`.".into(),
+            ));
+        }
+        if self.multiplier.is_nan() || self.multiplier < 1.0 {
+            return Err(ConfigError::Invalid(
+                "`openfga.retry.multiplier` must be at least 1.".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(ConfigError::Invalid(
+                "`openfga.retry.jitter` must be between 0 and 1.".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TlsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.client_cert_path.is_some() != self.client_key_path.is_some() {
+            return Err(ConfigError::Invalid(
+                "`openfga.tls.client_cert_path` and `openfga.tls.client_key_path` must be set \
+                 together."
+                    .into(),
+            ));
+        }
+        let paths = [
+            ("openfga.tls.ca_cert_path", &self.ca_cert_path),
+            ("openfga.tls.client_cert_path", &self.client_cert_path),
+            ("openfga.tls.client_key_path", &self.client_key_path),
+        ];
+        for (key, path) in paths {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    return Err(ConfigError::Invalid(format!(
+                        "`{}` {:?} is not a readable file.",
+                        key, path
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WebhooksConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_attempts == 0
+            || self.initial_backoff_ms == 0
+            || self.max_backoff_ms == 0
+            || self.timeout_ms == 0
+            || self.queue_capacity == 0
+        {
+            return Err(ConfigError::Invalid(
+                "`webhooks` values must be greater than zero.".into(),
+            ));
+        }
+        for endpoint in &self.endpoints {
+            let url = url::Url::parse(&endpoint.url).map_err(|error| {
+                ConfigError::Invalid(format!(
+                    "`webhooks.endpoints.url` {:?} is not a valid URL: {}",
+                    endpoint.url, error
+                ))
+            })?;
+            if !["http", "https"].contains(&url.scheme()) {
+                return Err(ConfigError::Invalid(format!(
+                    "`webhooks.endpoints.url` {:?} must be an `http` or `https` URL.",
+                    endpoint.url
+                )));
+            }
+            if endpoint.secret.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "`webhooks.endpoints.secret` is required for {:?}.",
+                    endpoint.url
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match &self.api_key {
+            Some(api_key) if !api_key.is_empty() => {}
+            _ => {
+                return Err(ConfigError::Invalid(
+                    "`server.api_key` (or `URKEL_BEARER_TOKEN`) is required.".into(),
+                ))
+            }
+        }
+        if let Some(address) = &self.address {
+            address.parse::<std::net::IpAddr>().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "`server.address` {:?} is not an IP address.",
+                    address
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Rocket's own configuration with the address and port overridden when they are set.
+    pub fn rocket_figment(&self) -> Figment {
+        let mut figment = rocket::Config::figment();
+        if let Some(address) = &self.address {
+            figment = figment.merge(("address", address));
+        }
+        if let Some(port) = self.port {
+            figment = figment.merge(("port", port));
+        }
+        figment
+    }
+}
+
+/// OpenFGA identifiers are ULIDs: 26 characters of Crockford's base32.
+fn validate_ulid(key: &str, value: &str) -> Result<(), ConfigError> {
+    let is_ulid = value.len() == 26
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || (c.is_ascii_uppercase() && !"ILOU".contains(c)));
+    if is_ulid {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(format!(
+            "`{}` {:?} is not a valid identifier.",
+            key, value
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE_ID: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("urkel-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn load(path: &str, env: &[(&str, &str)]) -> Result<UrkelConfig, ConfigError> {
+        UrkelConfig::from_figment(&UrkelConfig::layered(path, vars(env)))
+    }
+
+    #[test]
+    fn layers_the_file_and_the_environment_over_the_defaults() {
+        let path = config_file(
+            "layers",
+            "[openfga]\napi_url = \"http://file:8081\"\nmax_concurrent_checks = 4\n\
+             [openfga.credentials]\nmethod = \"api_token\"\napi_token = \"file\"\n\
+             [server]\napi_key = \"file\"\nport = 8000\n",
+        );
+
+        let config = load(&path, &[]).unwrap();
+        assert_eq!(config.openfga.api_url, "http://file:8081");
+        assert_eq!(config.openfga.max_concurrent_checks, 4);
+        assert_eq!(config.openfga.retry, RetryConfig::default());
+        assert_eq!(config.server.port, Some(8000));
+
+        let legacy = [
+            ("OPENFGA_ADDR", "http://legacy:8081"),
+            ("OPENFGA_BEARER_TOKEN", "legacy"),
+            ("URKEL_BEARER_TOKEN", "legacy"),
+        ];
+        let config = load(&path, &legacy).unwrap();
+        assert_eq!(config.openfga.api_url, "http://legacy:8081");
+        assert_eq!(
+            config.openfga.credentials.api_token.as_deref(),
+            Some("legacy")
+        );
+        assert_eq!(config.server.api_key.as_deref(), Some("legacy"));
+
+        let prefixed = [
+            legacy[0],
+            legacy[1],
+            legacy[2],
+            ("URKEL_OPENFGA__API_URL", "http://prefixed:8081"),
+            ("URKEL_OPENFGA__CREDENTIALS__API_TOKEN", "prefixed"),
+            ("urkel_server__api_key", "prefixed"),
+            ("URKEL_SERVER__PORT", "9000"),
+            ("URKEL_CONFIG", "elsewhere.toml"),
+        ];
+        let config = load(&path, &prefixed).unwrap();
+        assert_eq!(config.openfga.api_url, "http://prefixed:8081");
+        assert_eq!(
+            config.openfga.credentials.api_token.as_deref(),
+            Some("prefixed")
+        );
+        assert_eq!(config.server.api_key.as_deref(), Some("prefixed"));
+        assert_eq!(config.server.port, Some(9000));
+        assert_eq!(config.openfga.max_concurrent_checks, 4);
+    }
+
+    #[test]
+    fn reads_variables_as_strings() {
+        let path = config_file("strings", "");
+        let config = load(
+            &path,
+            &[
+                ("OPENFGA_BEARER_TOKEN", "0123"),
+                ("URKEL_SERVER__API_KEY", "0123"),
+                ("URKEL_OPENFGA__CREDENTIALS__METHOD", "api_token"),
+                ("URKEL_OPENFGA__MAX_CONCURRENT_CHECKS", "8"),
+                ("URKEL_OPENFGA__CACHE__ENABLED", "true"),
+                (
+                    "URKEL_OPENFGA__WATCHER__STORES",
+                    &format!("[\"{}\"]", STORE_ID),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.openfga.credentials.api_token.as_deref(),
+            Some("0123")
+        );
+        assert_eq!(config.server.api_key.as_deref(), Some("0123"));
+        assert_eq!(config.openfga.max_concurrent_checks, 8);
+        assert!(config.openfga.cache.enabled);
+        assert_eq!(config.openfga.watcher.stores, vec![STORE_ID.to_owned()]);
+
+        let config = load(
+            &path,
+            &[
+                ("URKEL_OPENFGA__CREDENTIALS__API_TOKEN", "0123"),
+                ("URKEL_BEARER_TOKEN", "key"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            config.openfga.credentials.api_token.as_deref(),
+            Some("0123")
+        );
+    }
+
+    #[test]
+    fn webhooks_require_the_watcher() {
+        let endpoint =
+            "[openfga.credentials]\napi_token = \"token\"\n[server]\napi_key = \"key\"\n\
+                        [[webhooks.endpoints]]\nurl = \"http://localhost:8000/changes\"\n\
+                        secret = \"secret\"\n";
+        let path = config_file("webhooks", endpoint);
+        let requires_watcher = |result| matches!(result, Err(ConfigError::Invalid(message)) if message.contains("watcher"));
+        assert!(requires_watcher(load(&path, &[])));
+
+        let path = config_file(
+            "webhooks-watched",
+            &format!(
+                "[openfga.watcher]\nenabled = true\nstores = [\"{}\"]\n{}",
+                STORE_ID, endpoint
+            ),
+        );
+        let config = load(&path, &[]).unwrap();
+        assert_eq!(config.webhooks.endpoints.len(), 1);
+        assert!(requires_watcher(load(
+            &path,
+            &[("URKEL_OPENFGA__WATCHER__ENABLED", "false")]
+        )));
+    }
+}
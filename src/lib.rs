@@ -7,4 +7,11 @@ extern crate serde_json;
 extern crate url;
 
 pub mod apis;
+pub mod config;
+// Generated from the OpenFGA OpenAPI spec, and left as the generator writes it.
+#[allow(
+    clippy::empty_docs,
+    clippy::to_string_trait_impl,
+    clippy::derivable_impls
+)]
 pub mod models;
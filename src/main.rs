@@ -1,17 +1,25 @@
 #[macro_use]
 extern crate rocket;
 use rocket::fairing::{Fairing, Info, Kind};
+use rocket::futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use rocket::http::Header;
-use rocket::http::Status;
-use rocket::response::status;
+use rocket::http::{Accept, ContentType, MediaType, Status};
+use rocket::response::stream::{Event, EventStream, TextStream};
+use rocket::response::{self, status, Responder};
 use rocket::serde::json::Json;
-use rocket::{Request, Response};
-use std::env;
+use rocket::serde::Serialize;
+use rocket::{Request, Response, State};
 
-pub struct CORS;
+pub struct CORS {
+    allowed_origin: String,
+}
 
 use rocket::request::{FromRequest, Outcome};
 
+#[allow(dead_code)]
 struct ApiKey<'r>(&'r str);
 
 #[derive(Debug)]
@@ -20,18 +28,19 @@ enum ApiKeyError {
     Invalid,
 }
 
-#[allow(unused_assignments)]
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ApiKey<'r> {
     type Error = ApiKeyError;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let mut token: String = "".to_owned();
-        if let Ok(token_result) = env::var("URKEL_BEARER_TOKEN") {
-            token = token_result;
-        } else {
-            return Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid));
-        }
+        let token = match req
+            .rocket()
+            .state::<urkel::config::ServerConfig>()
+            .and_then(|server| server.api_key.as_deref())
+        {
+            Some(token) => token,
+            None => return Outcome::Failure((Status::InternalServerError, ApiKeyError::Invalid)),
+        };
 
         match req.headers().get_one("X-URKEL-KEY") {
             None => Outcome::Failure((Status::Unauthorized, ApiKeyError::Missing)),
@@ -41,6 +50,107 @@ impl<'r> FromRequest<'r> for ApiKey<'r> {
     }
 }
 
+/// Whether the caller asked for decisions fresh from OpenFGA with `Cache-Control: no-cache` or
+/// `no-store`, bypassing the decision cache.
+struct CacheControl {
+    bypass: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CacheControl {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let bypass = req.headers().get("Cache-Control").any(|value| {
+            value
+                .split(',')
+                .map(|directive| directive.trim().to_ascii_lowercase())
+                .any(|directive| directive == "no-cache" || directive == "no-store")
+        });
+        Outcome::Success(CacheControl { bypass })
+    }
+}
+
+impl CacheControl {
+    fn client(&self, client: &urkel::apis::UrkelClient) -> urkel::apis::UrkelClient {
+        if self.bypass {
+            client.without_cache()
+        } else {
+            client.clone()
+        }
+    }
+}
+
+/// The id of the last event received by an `EventSource` reconnecting to a stream.
+struct LastEventId(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = req.headers().get_one("Last-Event-ID").map(str::to_owned);
+        Outcome::Success(LastEventId(id))
+    }
+}
+
+/// Answers with the HTTP status matching the OpenFGA error, logging the ones that are not the
+/// caller's fault.
+fn error_response(error: urkel::apis::UrkelError) -> status::Custom<Json<urkel::apis::UrkelError>> {
+    let status = Status::from_code(error.http_status()).unwrap_or(Status::InternalServerError);
+    if status.code >= 500 {
+        eprintln!("Internal Error: {error}");
+    }
+    status::Custom(status, Json(error))
+}
+
+/// A streamed answer, sent as server-sent events to clients accepting them and as
+/// newline-delimited JSON otherwise.
+enum Streamed {
+    Ndjson((ContentType, TextStream<BoxStream<'static, String>>)),
+    Events(EventStream<BoxStream<'static, Event>>),
+}
+
+impl Streamed {
+    /// Each item is sent as `{"result": ...}`, or as `{"error": ...}` in NDJSON and an `error`
+    /// event in SSE.
+    fn new<T: Serialize + Send + 'static>(
+        results: BoxStream<'static, Result<T, urkel::apis::UrkelError>>,
+        accept: Option<&Accept>,
+    ) -> Streamed {
+        let events =
+            accept.is_some_and(|accept| accept.preferred().media_type() == &MediaType::EventStream);
+        if events {
+            let events = results.map(|result| match result {
+                Ok(result) => Event::json(&result),
+                Err(error) => Event::json(&error).event("error"),
+            });
+            return Streamed::Events(EventStream::from(events.boxed()));
+        }
+
+        let lines = results.map(|result| {
+            let line = match result {
+                Ok(result) => serde_json::json!({ "result": result }),
+                Err(error) => serde_json::json!({ "error": error }),
+            };
+            format!("{line}\n")
+        });
+        Streamed::Ndjson((
+            ContentType::new("application", "x-ndjson"),
+            TextStream::from(lines.boxed()),
+        ))
+    }
+}
+
+impl<'r> Responder<'r, 'r> for Streamed {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        match self {
+            Streamed::Ndjson(lines) => lines.respond_to(request),
+            Streamed::Events(events) => events.respond_to(request),
+        }
+    }
+}
+
 /// Endpoints related to Stores
 /// Returns a paginated list of OpenFGA stores.
 #[get("/stores?<page_size>&<continuation_token>", format = "json")]
@@ -48,23 +158,30 @@ async fn list_stores(
     page_size: Option<i32>,
     continuation_token: Option<&str>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ListStoresResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::list_stores(page_size, continuation_token).await {
+    match client.list_stores(page_size, continuation_token).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Returns every OpenFGA store, following the continuation tokens.
+#[get("/stores-until-end?<page_size>", format = "json")]
+async fn list_stores_until_end(
+    page_size: Option<i32>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<
+    Json<urkel::apis::openfga::ListStoresResponse>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
+> {
+    match client.list_stores_until_end(page_size).await {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -73,23 +190,14 @@ async fn list_stores(
 async fn create_store(
     body: Json<urkel::apis::openfga::CreateStoreRequest>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::CreateStoreResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::create_store(body.into_inner()).await {
+    match client.create_store(body.into_inner()).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -98,23 +206,31 @@ async fn create_store(
 async fn get_store(
     store_id: &str,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::GetStoreResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::get_store(store_id).await {
+    match client.get_store(store_id).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Rename an OpenFGA store.
+#[patch("/stores/<store_id>", format = "json", data = "<body>")]
+async fn update_store(
+    store_id: &str,
+    body: Json<urkel::apis::openfga::UpdateStoreRequest>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<
+    Json<urkel::apis::openfga::UpdateStoreResponse>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
+> {
+    match client.update_store(store_id, body.into_inner()).await {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -123,20 +239,11 @@ async fn get_store(
 async fn delete_store(
     store_id: &str,
     _key: ApiKey<'_>,
-) -> Result<(), status::Custom<Json<urkel::models::InternalErrorMessageResponse>>> {
-    match urkel::apis::delete_store(store_id).await {
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<(), status::Custom<Json<urkel::apis::UrkelError>>> {
+    match client.delete_store(store_id).await {
         Ok(_) => Ok(()),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -152,23 +259,17 @@ async fn list_models(
     page_size: Option<i32>,
     continuation_token: Option<&str>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ReadAuthorizationModelsResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::read_authorization_models(store_id, page_size, continuation_token).await {
+    match client
+        .read_authorization_models(store_id, page_size, continuation_token)
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -184,23 +285,17 @@ async fn create_model(
     store_id: &str,
     body: Json<urkel::apis::openfga::WriteAuthorizationModelRequest>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::WriteAuthorizationModelResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::write_authorization_model(store_id, body.into_inner()).await {
+    match client
+        .write_authorization_model(store_id, body.into_inner())
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -211,23 +306,37 @@ async fn get_model(
     store_id: &str,
     id: &str,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ReadAuthorizationModelResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::read_authorization_model(store_id, id).await {
+    match client.read_authorization_model(store_id, id).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Returns every authorization model of a store, newest first, following the continuation tokens.
+#[get(
+    "/stores/<store_id>/authorization-models-until-end?<page_size>",
+    format = "json"
+)]
+async fn list_models_until_end(
+    store_id: &str,
+    page_size: Option<i32>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<
+    Json<urkel::apis::openfga::ReadAuthorizationModelsResponse>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
+> {
+    match client
+        .read_authorization_models_until_end(store_id, page_size)
+        .await
+    {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -248,26 +357,71 @@ async fn list_changes(
     page_size: Option<i32>,
     continuation_token: Option<&str>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ReadChangesResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::read_changes(store_id, r#type, page_size, continuation_token).await {
+    match client
+        .read_changes(store_id, r#type, page_size, continuation_token)
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
+/// Pushes the changes of a store as server-sent `change` events, optionally only those affecting
+/// objects of `type`. They start from `continuation_token`, the `Last-Event-ID` of a reconnecting
+/// client, or else the latest change. The last event of each page has the continuation token
+/// following the page as its id, so a reconnecting client receives the changes it missed. An
+/// `error` event ends the stream. The changes of a watched store are those published by the
+/// change watcher, OpenFGA only being read to catch up from a continuation token; other stores are
+/// polled for each client.
+#[allow(clippy::too_many_arguments)]
+#[get("/stores/<store_id>/changes/stream?<type>&<page_size>&<continuation_token>")]
+fn stream_changes(
+    store_id: &str,
+    r#type: Option<&str>,
+    page_size: Option<i32>,
+    continuation_token: Option<&str>,
+    last_event_id: LastEventId,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+    watcher: &State<Option<urkel::apis::ChangeWatcher>>,
+) -> EventStream<BoxStream<'static, Event>> {
+    let continuation_token = continuation_token.map(str::to_owned).or(last_event_id.0);
+    let continuation_token = continuation_token.as_deref();
+    let watched = watcher.as_ref().and_then(|watcher| {
+        watcher.changes(client, store_id, r#type, page_size, continuation_token)
+    });
+    let pages = match watched {
+        Some(pages) => pages,
+        None => client.watch_changes(store_id, r#type, page_size, continuation_token),
+    };
+    let events = pages.flat_map(|page| {
+        let events = match page {
+            Ok(page) => {
+                let last = page.changes.len().saturating_sub(1);
+                page.changes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, change)| {
+                        let event = Event::json(change).event("change");
+                        match index == last {
+                            true => event.id(page.continuation_token.clone()),
+                            false => event,
+                        }
+                    })
+                    .collect()
+            }
+            Err(error) => vec![Event::json(&error).event("error")],
+        };
+        stream::iter(events)
+    });
+    EventStream::from(events.boxed())
+}
+
 /// The Read API will return the tuples for a certain store that match a query filter specified in the body
 /// of the request. It is different from the /stores/{store_id}/expand API in that it only returns relationship
 /// tuples that are stored in the system and satisfy the query.
@@ -280,23 +434,12 @@ async fn read(
     store_id: &str,
     body: Json<urkel::apis::openfga::ReadRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::ReadResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::read(store_id, body.into_inner()).await {
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::openfga::ReadResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match client.read(store_id, body.into_inner()).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -313,23 +456,12 @@ async fn write(
     store_id: &str,
     body: Json<urkel::apis::openfga::WriteRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::WriteResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::write(store_id, body.into_inner()).await {
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::openfga::WriteResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match client.write(store_id, body.into_inner()).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -346,23 +478,17 @@ async fn check(
     store_id: &str,
     body: Json<urkel::apis::openfga::CheckRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::CheckResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::check(store_id, body.into_inner()).await {
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::openfga::CheckResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match cache
+        .client(client)
+        .check(store_id, body.into_inner())
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -377,23 +503,12 @@ async fn expand(
     store_id: &str,
     body: Json<urkel::apis::openfga::ExpandRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::ExpandResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::expand(store_id, body.into_inner()).await {
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::openfga::ExpandResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match client.expand(store_id, body.into_inner()).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -411,23 +526,38 @@ async fn list_objects(
     store_id: &str,
     body: Json<urkel::apis::openfga::ListObjectsRequest>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ListObjectsResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::list_objects(store_id, body.into_inner()).await {
+    match client.list_objects(store_id, body.into_inner()).await {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Streaming variant of ListObjects, answering with each object as soon as OpenFGA finds it and
+/// without ListObjects' limit on the number of results. Results are sent as server-sent events
+/// when `Accept` prefers `text/event-stream`, and as newline-delimited JSON otherwise.
+#[post(
+    "/stores/<store_id>/streamed-list-objects",
+    format = "json",
+    data = "<body>"
+)]
+async fn streamed_list_objects(
+    store_id: &str,
+    body: Json<urkel::apis::openfga::StreamedListObjectsRequest>,
+    accept: Option<&Accept>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Streamed, status::Custom<Json<urkel::apis::UrkelError>>> {
+    match client
+        .streamed_list_objects(store_id, body.into_inner())
+        .await
+    {
+        Ok(results) => Ok(Streamed::new(results, accept)),
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -442,23 +572,17 @@ async fn list_assertions(
     store_id: &str,
     authorization_model_id: &str,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
     Json<urkel::apis::openfga::ReadAssertionsResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::read_assertions(store_id, authorization_model_id).await {
+    match client
+        .read_assertions(store_id, authorization_model_id)
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -475,67 +599,112 @@ async fn create_assertions(
     authorization_model_id: &str,
     body: Json<urkel::apis::openfga::WriteAssertionsRequest>,
     _key: ApiKey<'_>,
-) -> Result<(), status::Custom<Json<urkel::models::InternalErrorMessageResponse>>> {
-    match urkel::apis::write_assertions(store_id, authorization_model_id, body.into_inner()).await {
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<(), status::Custom<Json<urkel::apis::UrkelError>>> {
+    match client
+        .write_assertions(store_id, authorization_model_id, body.into_inner())
+        .await
+    {
         Ok(_) => Ok(()),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
-#[post("/stores/<store_id>/read-until-end", format = "json", data = "<body>")]
-async fn read_until_end(
+/// Returns every change after `continuation_token`, following the continuation tokens. The
+/// returned token is the one to use for later changes.
+#[get(
+    "/stores/<store_id>/changes-until-end?<type>&<page_size>&<continuation_token>",
+    format = "json"
+)]
+async fn list_changes_until_end(
     store_id: &str,
-    body: Json<urkel::apis::openfga::ReadRequest>,
+    r#type: Option<&str>,
+    page_size: Option<i32>,
+    continuation_token: Option<&str>,
     _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Result<
-    Json<urkel::apis::openfga::ReadResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
+    Json<urkel::apis::openfga::ReadChangesResponse>,
+    status::Custom<Json<urkel::apis::UrkelError>>,
 > {
-    match urkel::apis::read_until_end(store_id, body.into_inner()).await {
+    match client
+        .read_changes_until_end(store_id, r#type, page_size, continuation_token)
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
+/// Reads the tuples matching the filter, following the continuation tokens up to `max_tuples`
+/// tuples or `max_pages` pages, and the configured limits. A truncated response carries the
+/// continuation token resuming the read.
+#[post(
+    "/stores/<store_id>/read-until-end?<max_tuples>&<max_pages>",
+    format = "json",
+    data = "<body>"
+)]
+async fn read_until_end(
+    store_id: &str,
+    max_tuples: Option<usize>,
+    max_pages: Option<usize>,
+    body: Json<urkel::apis::openfga::ReadRequest>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::ReadUntilEndResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    let limits = urkel::apis::ReadLimits {
+        max_tuples,
+        max_pages,
+    };
+    match client
+        .read_until_end(store_id, body.into_inner(), limits)
+        .await
+    {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Streaming variant of read-until-end, sending each tuple as soon as its page is read and then
+/// where the read stopped, as `{"result": {"tuple": ...}}` and `{"result": {"end": ...}}` lines.
+#[post(
+    "/stores/<store_id>/read-until-end/stream?<max_tuples>&<max_pages>",
+    format = "json",
+    data = "<body>"
+)]
+async fn read_until_end_stream(
+    store_id: &str,
+    max_tuples: Option<usize>,
+    max_pages: Option<usize>,
+    body: Json<urkel::apis::openfga::ReadRequest>,
+    accept: Option<&Accept>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Streamed {
+    let limits = urkel::apis::ReadLimits {
+        max_tuples,
+        max_pages,
+    };
+    let items = client.read_until_end_stream(store_id, body.into_inner(), limits);
+    Streamed::new(items, accept)
+}
+
+/// Runs several checks at once, answering in the order of the checks. The body is either an array
+/// of checks, each with an optional `correlation_id`, or an object with these `checks` and a
+/// `max_concurrent_checks` lowering the configured one.
 #[post("/stores/<store_id>/batch-check", format = "json", data = "<body>")]
 async fn batch_check(
     store_id: &str,
-    body: Json<Vec<urkel::apis::openfga::CheckRequest>>,
+    body: Json<urkel::apis::BatchCheckBody>,
     _key: ApiKey<'_>,
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
 ) -> Json<Vec<urkel::apis::BatchCheckResponse>> {
-    let results: Vec<Result<urkel::apis::BatchCheckResponse, urkel::apis::BatchCheckResponse>> =
-        urkel::apis::batch_check(store_id, body.into_inner()).await;
-    let results = results
-        .into_iter()
-        .map(|result| match result {
-            Ok(tonic_response) => tonic_response,
-            Err(error) => urkel::apis::BatchCheckResponse {
-                allowed: Some(false),
-                request: None,
-                err: error.err,
-            },
-        })
-        .collect::<Vec<_>>();
+    let results = cache
+        .client(client)
+        .batch_check(store_id, body.into_inner().into())
+        .await;
     Json(results)
 }
 
@@ -544,23 +713,63 @@ async fn check_n_of_m(
     store_id: &str,
     body: Json<urkel::apis::CheckNOfMRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::CheckResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::check_n_of_m(store_id, body.into_inner()).await {
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::ThresholdCheckResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match cache
+        .client(client)
+        .check_n_of_m(store_id, body.into_inner())
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Allows when the summed weights of the allowed checks reach the threshold, given either as a
+/// `weight` or as a `percentage` of the total weight.
+#[post("/stores/<store_id>/check-weighted", format = "json", data = "<body>")]
+async fn check_weighted(
+    store_id: &str,
+    body: Json<urkel::apis::CheckWeightedRequest>,
+    _key: ApiKey<'_>,
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::ThresholdCheckResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match cache
+        .client(client)
+        .check_weighted(store_id, body.into_inner())
+        .await
+    {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Evaluates a policy expression nesting `all`, `any`, `not` and `at_least` over checks, e.g.
+/// `{"all": [{"check": ...}, {"any": [{"check": ...}, {"at_least": {"n": 2, "of": [...]}}]}]}`.
+#[post(
+    "/stores/<store_id>/check-expression",
+    format = "json",
+    data = "<body>"
+)]
+async fn check_expression(
+    store_id: &str,
+    body: Json<urkel::apis::PolicyExpression>,
+    _key: ApiKey<'_>,
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::openfga::CheckResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match cache
+        .client(client)
+        .check_expression(store_id, body.into_inner())
+        .await
+    {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
     }
 }
 
@@ -573,26 +782,59 @@ async fn check_horizontal(
     store_id: &str,
     body: Json<urkel::apis::CheckHorizontalRequest>,
     _key: ApiKey<'_>,
-) -> Result<
-    Json<urkel::apis::openfga::CheckResponse>,
-    status::Custom<Json<urkel::models::InternalErrorMessageResponse>>,
-> {
-    match urkel::apis::check_horizontal(store_id, body.into_inner()).await {
+    cache: CacheControl,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::CheckHorizontalResponse>, status::Custom<Json<urkel::apis::UrkelError>>>
+{
+    match cache
+        .client(client)
+        .check_horizontal(store_id, body.into_inner())
+        .await
+    {
         Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
-        Err(error) => {
-            eprintln!("Internal Error: {error}");
-            let internal_error = urkel::models::InternalErrorMessageResponse {
-                code: Some(urkel::models::InternalErrorCode::InternalError),
-                message: Some(error.to_string()),
-            };
-            Err(status::Custom(
-                Status::InternalServerError,
-                Json(internal_error),
-            ))
-        }
+        Err(error) => Err(error_response(error)),
     }
 }
 
+/// Lists the users having a relation with an object, e.g. who can view a document, by expanding
+/// the relation through its computed usersets, tuple-to-usersets and groups. `user_type` keeps the
+/// users of a single type.
+#[post("/stores/<store_id>/list-users", format = "json", data = "<body>")]
+async fn list_users(
+    store_id: &str,
+    body: Json<urkel::apis::ListUsersRequest>,
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Result<Json<urkel::apis::ListUsersResponse>, status::Custom<Json<urkel::apis::UrkelError>>> {
+    match client.list_users(store_id, body.into_inner()).await {
+        Ok(tonic_response) => Ok(Json(tonic_response.into_inner())),
+        Err(error) => Err(error_response(error)),
+    }
+}
+
+/// Deliveries of each configured webhook since Urkel started.
+#[get("/admin/webhooks", format = "json")]
+fn webhook_statuses(
+    _key: ApiKey<'_>,
+    webhooks: &State<Option<urkel::apis::WebhookDispatcher>>,
+) -> Json<Vec<urkel::apis::WebhookStatus>> {
+    Json(
+        webhooks
+            .as_ref()
+            .map_or_else(Vec::new, |webhooks| webhooks.statuses()),
+    )
+}
+
+/// How the checks were answered since Urkel started, including the checks coalesced with an
+/// identical check in flight.
+#[get("/admin/checks", format = "json")]
+fn check_metrics(
+    _key: ApiKey<'_>,
+    client: &State<urkel::apis::UrkelClient>,
+) -> Json<urkel::apis::CheckMetrics> {
+    Json(client.check_metrics())
+}
+
 #[catch(404)]
 fn not_found() -> Json<urkel::models::PathUnknownErrorMessageResponse> {
     let path_error = urkel::models::PathUnknownErrorMessageResponse {
@@ -643,8 +885,8 @@ fn default_catcher() -> Json<urkel::models::InternalErrorMessageResponse> {
 
 /// Catches all OPTION requests in order to get the CORS related Fairing triggered.
 #[options("/<_..>")]
-fn all_options() {
-    /* Intentionally left empty */
+fn all_options() -> Status {
+    Status::Ok
 }
 
 #[rocket::async_trait]
@@ -657,7 +899,10 @@ impl Fairing for CORS {
     }
 
     async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            self.allowed_origin.clone(),
+        ));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
             "POST, GET, PATCH, OPTIONS, DELETE",
@@ -668,30 +913,66 @@ impl Fairing for CORS {
 }
 
 #[launch]
-fn rocket() -> _ {
-    rocket::build()
+async fn rocket() -> _ {
+    let config = urkel::config::UrkelConfig::load().unwrap_or_else(|error| panic!("{error}"));
+    let client =
+        urkel::apis::UrkelClient::from_config(&config.openfga).expect("building OpenFGA client");
+    let watcher = match config.openfga.watcher.enabled {
+        true => Some(
+            urkel::apis::ChangeWatcher::spawn(&client)
+                .await
+                .expect("starting the change watcher"),
+        ),
+        false => None,
+    };
+    let webhooks = match &watcher {
+        Some(watcher) if !config.webhooks.endpoints.is_empty() => Some(
+            urkel::apis::WebhookDispatcher::spawn(&client, watcher, &config.webhooks)
+                .expect("starting the webhooks"),
+        ),
+        _ => None,
+    };
+
+    rocket::custom(config.server.rocket_figment())
+        .manage(client)
+        .manage(config.server.clone())
+        // Managed even when disabled: routes needing a missing state abort the launch.
+        .manage(watcher)
+        .manage(webhooks)
         .mount(
             "/",
             routes![
                 list_stores,
+                list_stores_until_end,
                 create_store,
                 get_store,
+                update_store,
                 delete_store,
                 list_models,
+                list_models_until_end,
                 create_model,
                 get_model,
                 list_changes,
+                stream_changes,
                 read,
                 write,
                 check,
                 expand,
                 list_objects,
+                streamed_list_objects,
                 list_assertions,
                 read_until_end,
+                read_until_end_stream,
+                list_changes_until_end,
                 create_assertions,
                 batch_check,
                 check_n_of_m,
+                check_weighted,
+                check_expression,
                 check_horizontal,
+                list_users,
+                webhook_statuses,
+                check_metrics,
                 all_options
             ],
         )
@@ -705,5 +986,7 @@ fn rocket() -> _ {
                 unauthorized
             ],
         )
-        .attach(CORS)
+        .attach(CORS {
+            allowed_origin: config.server.allowed_origin,
+        })
 }